@@ -0,0 +1,454 @@
+//! Algorithms pairing up the items of two arrays, used to diff arrays.
+//!
+//! Paired items are kept, possibly with a nested change, while the other items are added, removed
+//! or moved.
+
+use crate::lcs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The items of the two arrays being diffed, as seen by an [`ArrayMatcher`]
+pub trait ArrayItems {
+    fn left_len(&self) -> usize;
+
+    fn right_len(&self) -> usize;
+
+    /// Whether the two items are the same item, possibly with a modified content
+    fn matches(&self, left_index: usize, right_index: usize) -> bool;
+
+    /// A key of a left item, equal to the key of any right item it matches
+    fn left_key(&self, left_index: usize) -> u64;
+
+    /// A key of a right item, equal to the key of any left item it matches
+    fn right_key(&self, right_index: usize) -> u64;
+}
+
+/// Pairs up the items of two arrays.
+///
+/// Returns the pairs of (left index, right index) of matching items, in increasing order of both
+/// indices.
+pub trait ArrayMatcher {
+    fn match_items(&self, items: &dyn ArrayItems) -> Vec<(usize, usize)>;
+}
+
+/// The array diff algorithm to use, see [`crate::types::ArrayOptions`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArrayDiffAlgorithm {
+    /// Longest common subsequence, giving the fewest changes
+    #[default]
+    Lcs,
+    /// Patience diff, anchored on items that are unique in both arrays
+    Patience,
+    /// Histogram diff, anchored on the least frequent items
+    Histogram,
+    /// Pairs items found at the same index
+    Positional,
+}
+
+impl ArrayDiffAlgorithm {
+    pub fn matcher(&self) -> &'static dyn ArrayMatcher {
+        match self {
+            ArrayDiffAlgorithm::Lcs => &Lcs,
+            ArrayDiffAlgorithm::Patience => &Patience,
+            ArrayDiffAlgorithm::Histogram => &Histogram,
+            ArrayDiffAlgorithm::Positional => &Positional,
+        }
+    }
+}
+
+/// Matches the longest common subsequence of items
+pub struct Lcs;
+
+impl ArrayMatcher for Lcs {
+    fn match_items(&self, items: &dyn ArrayItems) -> Vec<(usize, usize)> {
+        longest_common_subsequence(items, 0..items.left_len(), 0..items.right_len())
+    }
+}
+
+/// Matches items by their index, whatever their content
+pub struct Positional;
+
+impl ArrayMatcher for Positional {
+    fn match_items(&self, items: &dyn ArrayItems) -> Vec<(usize, usize)> {
+        (0..items.left_len().min(items.right_len()))
+            .map(|index| (index, index))
+            .collect()
+    }
+}
+
+/// Matches the items that occur exactly once in both arrays first (in their longest increasing
+/// sequence), then the items in between them. Ranges without such unique items fall back to the
+/// longest common subsequence.
+///
+/// Reordered lists of unique records get deltas that follow the records, rather than repeated
+/// values that happen to line up.
+pub struct Patience;
+
+impl ArrayMatcher for Patience {
+    fn match_items(&self, items: &dyn ArrayItems) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let mut pending = vec![(0..items.left_len(), 0..items.right_len())];
+
+        while let Some((left_range, right_range)) = pending.pop() {
+            let (left_range, right_range) =
+                match_common_ends(items, left_range, right_range, &mut pairs);
+            if left_range.is_empty() || right_range.is_empty() {
+                continue;
+            }
+
+            let anchors = unique_anchors(items, &left_range, &right_range);
+            if anchors.is_empty() {
+                pairs.extend(longest_common_subsequence(items, left_range, right_range));
+                continue;
+            }
+
+            let (mut left_start, mut right_start) = (left_range.start, right_range.start);
+            for (left_index, right_index) in anchors {
+                pending.push((left_start..left_index, right_start..right_index));
+                pairs.push((left_index, right_index));
+                (left_start, right_start) = (left_index + 1, right_index + 1);
+            }
+            pending.push((left_start..left_range.end, right_start..right_range.end));
+        }
+
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+/// Items occurring more often than this in the left range are not used as anchors by
+/// [`Histogram`]
+const MAX_HISTOGRAM_OCCURRENCES: usize = 64;
+
+/// Matches the longest run of common items containing the least frequent item of the left array,
+/// then the ranges before and after it. Ranges without any common item occurring at most
+/// `MAX_HISTOGRAM_OCCURRENCES` times fall back to the longest common subsequence.
+///
+/// Similar to patience diff, while also anchoring on items that are not unique.
+pub struct Histogram;
+
+impl ArrayMatcher for Histogram {
+    fn match_items(&self, items: &dyn ArrayItems) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let mut pending = vec![(0..items.left_len(), 0..items.right_len())];
+
+        while let Some((left_range, right_range)) = pending.pop() {
+            let (left_range, right_range) =
+                match_common_ends(items, left_range, right_range, &mut pairs);
+            if left_range.is_empty() || right_range.is_empty() {
+                continue;
+            }
+
+            let Some((left_run, right_run)) = least_frequent_run(items, &left_range, &right_range)
+            else {
+                pairs.extend(longest_common_subsequence(items, left_range, right_range));
+                continue;
+            };
+
+            pairs.extend(left_run.clone().zip(right_run.clone()));
+            pending.push((
+                left_range.start..left_run.start,
+                right_range.start..right_run.start,
+            ));
+            pending.push((left_run.end..left_range.end, right_run.end..right_range.end));
+        }
+
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+fn longest_common_subsequence(
+    items: &dyn ArrayItems,
+    left_range: Range<usize>,
+    right_range: Range<usize>,
+) -> Vec<(usize, usize)> {
+    lcs::longest_common_subsequence_by(left_range.len(), right_range.len(), |i, j| {
+        items.matches(left_range.start + i, right_range.start + j)
+    })
+    .into_iter()
+    .map(|(i, j)| (left_range.start + i, right_range.start + j))
+    .collect()
+}
+
+/// Matches the common head and tail of the ranges, returning the ranges left in between
+fn match_common_ends(
+    items: &dyn ArrayItems,
+    mut left_range: Range<usize>,
+    mut right_range: Range<usize>,
+    pairs: &mut Vec<(usize, usize)>,
+) -> (Range<usize>, Range<usize>) {
+    while !left_range.is_empty()
+        && !right_range.is_empty()
+        && items.matches(left_range.start, right_range.start)
+    {
+        pairs.push((left_range.start, right_range.start));
+        left_range.start += 1;
+        right_range.start += 1;
+    }
+    while !left_range.is_empty()
+        && !right_range.is_empty()
+        && items.matches(left_range.end - 1, right_range.end - 1)
+    {
+        left_range.end -= 1;
+        right_range.end -= 1;
+        pairs.push((left_range.end, right_range.end));
+    }
+    (left_range, right_range)
+}
+
+/// The pairs of matching items occurring once in both ranges, in their longest sequence that is
+/// increasing in both indices
+fn unique_anchors(
+    items: &dyn ArrayItems,
+    left_range: &Range<usize>,
+    right_range: &Range<usize>,
+) -> Vec<(usize, usize)> {
+    // occurrences of each key, along with the index of its last occurrence
+    let mut left_occurrences: HashMap<u64, (usize, usize)> = HashMap::new();
+    for left_index in left_range.clone() {
+        let entry = left_occurrences
+            .entry(items.left_key(left_index))
+            .or_insert((0, left_index));
+        *entry = (entry.0 + 1, left_index);
+    }
+    let mut right_occurrences: HashMap<u64, (usize, usize)> = HashMap::new();
+    for right_index in right_range.clone() {
+        let entry = right_occurrences
+            .entry(items.right_key(right_index))
+            .or_insert((0, right_index));
+        *entry = (entry.0 + 1, right_index);
+    }
+
+    let mut candidates: Vec<(usize, usize)> = left_occurrences
+        .iter()
+        .filter_map(
+            |(key, &(count, left_index))| match right_occurrences.get(key) {
+                Some(&(1, right_index)) if count == 1 => Some((left_index, right_index)),
+                _ => None,
+            },
+        )
+        .filter(|&(left_index, right_index)| items.matches(left_index, right_index))
+        .collect();
+    candidates.sort_unstable();
+
+    longest_increasing_sequence(&candidates)
+}
+
+/// The longest subsequence of pairs (sorted by left index) whose right indices are increasing,
+/// found with patience sorting
+fn longest_increasing_sequence(pairs: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // the index in `pairs` of the last pair of the best sequence of each length
+    let mut pile_tops: Vec<usize> = Vec::new();
+    let mut predecessors: Vec<Option<usize>> = Vec::with_capacity(pairs.len());
+
+    for (index, &(_, right_index)) in pairs.iter().enumerate() {
+        let pile = pile_tops.partition_point(|&top| pairs[top].1 < right_index);
+        predecessors.push(pile.checked_sub(1).map(|previous| pile_tops[previous]));
+        if pile == pile_tops.len() {
+            pile_tops.push(index);
+        } else {
+            pile_tops[pile] = index;
+        }
+    }
+
+    let mut sequence = Vec::with_capacity(pile_tops.len());
+    let mut current = pile_tops.last().copied();
+    while let Some(index) = current {
+        sequence.push(pairs[index]);
+        current = predecessors[index];
+    }
+    sequence.reverse();
+    sequence
+}
+
+/// The longest run of matching items around the least frequent item of the left range that is
+/// also found in the right range
+fn least_frequent_run(
+    items: &dyn ArrayItems,
+    left_range: &Range<usize>,
+    right_range: &Range<usize>,
+) -> Option<(Range<usize>, Range<usize>)> {
+    let mut left_occurrences: HashMap<u64, Vec<usize>> = HashMap::new();
+    for left_index in left_range.clone() {
+        left_occurrences
+            .entry(items.left_key(left_index))
+            .or_default()
+            .push(left_index);
+    }
+
+    // (occurrences, left run, right run) of the best run so far
+    let mut best: Option<(usize, Range<usize>, Range<usize>)> = None;
+    let mut right_index = right_range.start;
+    while right_index < right_range.end {
+        let mut next_right_index = right_index + 1;
+        let Some(left_indices) = left_occurrences.get(&items.right_key(right_index)) else {
+            right_index = next_right_index;
+            continue;
+        };
+        if left_indices.len() > MAX_HISTOGRAM_OCCURRENCES {
+            right_index = next_right_index;
+            continue;
+        }
+
+        for &left_index in left_indices {
+            if !items.matches(left_index, right_index) {
+                continue;
+            }
+            let (mut left_start, mut right_start) = (left_index, right_index);
+            while left_start > left_range.start
+                && right_start > right_range.start
+                && items.matches(left_start - 1, right_start - 1)
+            {
+                left_start -= 1;
+                right_start -= 1;
+            }
+            let (mut left_end, mut right_end) = (left_index + 1, right_index + 1);
+            while left_end < left_range.end
+                && right_end < right_range.end
+                && items.matches(left_end, right_end)
+            {
+                left_end += 1;
+                right_end += 1;
+            }
+
+            let is_better = best.as_ref().is_none_or(|(occurrences, left_run, _)| {
+                left_indices.len() < *occurrences
+                    || (left_indices.len() == *occurrences
+                        && left_end - left_start > left_run.len())
+            });
+            if is_better {
+                best = Some((
+                    left_indices.len(),
+                    left_start..left_end,
+                    right_start..right_end,
+                ));
+            }
+            next_right_index = next_right_index.max(right_end);
+        }
+        right_index = next_right_index;
+    }
+
+    best.map(|(_, left_run, right_run)| (left_run, right_run))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ArrayOptions;
+    use crate::{DiffPatcher, Options};
+    use serde_json::{json, Value};
+
+    struct ValueItems<'a> {
+        left: &'a [Value],
+        right: &'a [Value],
+    }
+
+    impl ArrayItems for ValueItems<'_> {
+        fn left_len(&self) -> usize {
+            self.left.len()
+        }
+
+        fn right_len(&self) -> usize {
+            self.right.len()
+        }
+
+        fn matches(&self, left_index: usize, right_index: usize) -> bool {
+            self.left[left_index] == self.right[right_index]
+        }
+
+        fn left_key(&self, left_index: usize) -> u64 {
+            crate::subtree_hash::value_hash(&self.left[left_index])
+        }
+
+        fn right_key(&self, right_index: usize) -> u64 {
+            crate::subtree_hash::value_hash(&self.right[right_index])
+        }
+    }
+
+    const ALGORITHMS: [ArrayDiffAlgorithm; 4] = [
+        ArrayDiffAlgorithm::Lcs,
+        ArrayDiffAlgorithm::Patience,
+        ArrayDiffAlgorithm::Histogram,
+        ArrayDiffAlgorithm::Positional,
+    ];
+
+    #[test]
+    fn test_array_matchers() {
+        let cases = [
+            (json!([]), json!([1])),
+            (json!([1, 2, 3]), json!([1, 2, 3])),
+            (json!([1, 2, 3, 4, 5]), json!([5, 1, 2, 4, 3])),
+            (
+                json!(["}", "a", "}", "b", "}"]),
+                json!(["b", "}", "}", "a", "}", "c"]),
+            ),
+            (
+                json!([{"id": 1}, {"id": 2}, 1, 1, 1]),
+                json!([1, 1, {"id": 2}, {"id": 1}]),
+            ),
+        ];
+
+        for algorithm in ALGORITHMS {
+            let diffpatcher = DiffPatcher::new(Some(Options {
+                arrays: Some(ArrayOptions {
+                    detect_move: Some(true),
+                    include_value_on_move: Some(false),
+                    algorithm: Some(algorithm),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }));
+
+            for (left, right) in &cases {
+                let (left_items, right_items) =
+                    (left.as_array().unwrap(), right.as_array().unwrap());
+                let items = ValueItems {
+                    left: left_items,
+                    right: right_items,
+                };
+                let pairs = algorithm.matcher().match_items(&items);
+                for window in pairs.windows(2) {
+                    assert!(window[0].0 < window[1].0 && window[0].1 < window[1].1);
+                }
+                if algorithm != ArrayDiffAlgorithm::Positional {
+                    assert!(pairs.iter().all(|&(i, j)| items.matches(i, j)));
+                }
+
+                let delta = diffpatcher.diff(left, right);
+                let patched = match delta {
+                    Some(delta) => diffpatcher.patch(left, delta),
+                    None => Some(left.clone()),
+                };
+                assert_eq!(
+                    patched.as_ref(),
+                    Some(right),
+                    "{:?}: {} -> {}",
+                    algorithm,
+                    left,
+                    right
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_patience_anchors_on_unique_items() {
+        let left = json!(["a", "}", "}", "}", "b"]);
+        let right = json!(["b", "}", "}", "}", "a"]);
+        let items = ValueItems {
+            left: left.as_array().unwrap(),
+            right: right.as_array().unwrap(),
+        };
+
+        assert_eq!(Lcs.match_items(&items), vec![(1, 1), (2, 2), (3, 3)]);
+        assert_eq!(Patience.match_items(&items).len(), 1);
+        assert_eq!(Histogram.match_items(&items).len(), 1);
+        assert_eq!(
+            Positional.match_items(&items),
+            vec![(0, 0), (1, 1), (2, 2), (3, 3), (4, 4)]
+        );
+    }
+}
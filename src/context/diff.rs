@@ -1,12 +1,20 @@
 use crate::context::{ContextData, FilterContext};
-use crate::types::Delta;
+use crate::path::PathSegment;
+use crate::subtree_hash::SubtreeHashes;
+use crate::types::{Delta, Options};
 use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct DiffContext<'a> {
     context_data: ContextData<Self>,
-    pub left: &'a Value,
-    pub right: &'a Value,
+    /// The left value, `None` when diffing an object property that is missing on the left
+    pub left: Option<&'a Value>,
+    /// The right value, `None` when diffing an object property that is missing on the right
+    pub right: Option<&'a Value>,
+    subtree_hashes: Option<Arc<SubtreeHashes>>,
+    path: Option<Arc<PathSegment>>,
 }
 
 impl<'a> FilterContext for DiffContext<'a> {
@@ -27,11 +35,208 @@ impl<'a> FilterContext for DiffContext<'a> {
 }
 
 impl<'a> DiffContext<'a> {
-    pub fn new(left: &'a Value, right: &'a Value) -> Self {
+    pub fn new(left: &'a Value, right: &'a Value, options: Arc<Options>) -> Self {
+        // the hashes cover whole values, they cannot tell apart values that only differ by
+        // filtered properties
+        let subtree_hashes = (options.hash_subtrees.unwrap_or(false)
+            && options.property_filter.is_none())
+        .then(|| Arc::new(SubtreeHashes::new(&[left, right])));
+
+        Self {
+            left: Some(left),
+            right: Some(right),
+            subtree_hashes,
+            path: None,
+            context_data: ContextData::new(options),
+        }
+    }
+
+    /// Creates a context for a nested pair of values, sharing the options of this context
+    pub fn new_child(&self, left: &'a Value, right: &'a Value) -> Self {
+        self.new_property_child(Some(left), Some(right))
+    }
+
+    /// Creates a context for an object property, which may be missing on either side
+    pub fn new_property_child(&self, left: Option<&'a Value>, right: Option<&'a Value>) -> Self {
         Self {
             left,
             right,
-            context_data: ContextData::new(),
+            subtree_hashes: self.subtree_hashes.clone(),
+            path: self.path.clone(),
+            context_data: ContextData::new(Arc::clone(self.context_data.shared_options())),
+        }
+    }
+
+    /// The object keys and array indices (in the right array) leading to the diffed values, empty
+    /// for the root values
+    pub fn path(&self) -> Vec<String> {
+        PathSegment::segments(self.path.as_ref())
+    }
+
+    /// Appends an object key or array index to the path of this context
+    pub(crate) fn push_path_segment(&mut self, name: &str) {
+        self.path = Some(PathSegment::new(self.path.take(), name));
+    }
+
+    /// Whether subtree hashes were computed, making equality checks cheap for unequal values
+    pub fn has_subtree_hashes(&self) -> bool {
+        self.subtree_hashes.is_some()
+    }
+
+    /// The hash of a value of the diffed documents, when subtree hashes were computed
+    pub(crate) fn subtree_hash(&self, value: &Value) -> Option<u64> {
+        self.subtree_hashes
+            .as_ref()
+            .and_then(|subtree_hashes| subtree_hashes.get(value))
+    }
+
+    /// Whether the two values are known to be different from their subtree hashes
+    fn hashes_differ(&self, left: &Value, right: &Value) -> bool {
+        let Some(subtree_hashes) = &self.subtree_hashes else {
+            return false;
+        };
+        match (subtree_hashes.get(left), subtree_hashes.get(right)) {
+            (Some(left_hash), Some(right_hash)) => left_hash != right_hash,
+            _ => false,
+        }
+    }
+
+    /// Whether removed values are left out of the delta, see `Options::omit_removed_values`
+    pub(crate) fn omit_removed_values(&self) -> bool {
+        self.options().omit_removed_values.unwrap_or(false)
+    }
+
+    /// A value to store in the delta: borrowed from the diffed documents, or copied with
+    /// `clone_diff_values` so that the delta does not depend on them
+    pub fn delta_value(&self, value: &'a Value) -> Cow<'a, Value> {
+        if self.options().clone_diff_values.unwrap_or(false) {
+            Cow::Owned(value.clone())
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    /// An addition of `value`
+    pub fn added_delta(&self, value: &'a Value) -> Delta<'a> {
+        Delta::Added(self.delta_value(value))
+    }
+
+    /// A deletion of `value`, which is left out of the delta with `omit_removed_values`
+    pub fn deleted_delta(&self, value: &'a Value) -> Delta<'a> {
+        if self.omit_removed_values() {
+            Delta::Deleted(None)
+        } else {
+            Delta::Deleted(Some(self.delta_value(value)))
+        }
+    }
+
+    /// A modification from `old_value` to `new_value`, where `old_value` is left out of the delta
+    /// with `omit_removed_values`
+    pub fn modified_delta(&self, old_value: &'a Value, new_value: &'a Value) -> Delta<'a> {
+        let old_value = (!self.omit_removed_values()).then(|| self.delta_value(old_value));
+        Delta::Modified(old_value, self.delta_value(new_value))
+    }
+
+    /// The value to keep along with a move, if any
+    pub fn moved_value(&self, value: &'a Value) -> Option<Cow<'a, Value>> {
+        let include_value_on_move = self
+            .options()
+            .arrays
+            .as_ref()
+            .and_then(|opts| opts.include_value_on_move)
+            .unwrap_or(false);
+        (include_value_on_move && !self.omit_removed_values()).then(|| self.delta_value(value))
+    }
+
+    /// Whether two values are equal, ignoring the object properties rejected by the
+    /// `property_filter` option
+    pub fn values_equal(&self, left: &'a Value, right: &'a Value) -> bool {
+        self.values_equal_at(self.path.clone(), left, right)
+    }
+
+    /// Whether two values found under the object key or array index `name` of the diffed values
+    /// are equal, see [`DiffContext::values_equal`]
+    pub(crate) fn child_values_equal(&self, name: &str, left: &'a Value, right: &'a Value) -> bool {
+        let path = self
+            .options()
+            .property_filter
+            .is_some()
+            .then(|| PathSegment::new(self.path.clone(), name));
+        self.values_equal_at(path, left, right)
+    }
+
+    /// Whether two values found at `path` are equal, the path being only tracked for the
+    /// `property_filter` option
+    fn values_equal_at(
+        &self,
+        path: Option<Arc<PathSegment>>,
+        left: &'a Value,
+        right: &'a Value,
+    ) -> bool {
+        let property_filter = self.options().property_filter.as_ref();
+        // compared with an explicit stack, as deeply nested values would overflow a recursive `==`
+        let mut pending = vec![(left, right, path)];
+
+        while let Some((left, right, path)) = pending.pop() {
+            // equal hashes may still collide, so only different ones are conclusive
+            if self.hashes_differ(left, right) {
+                return false;
+            }
+            match (left, right) {
+                (Value::Object(left_object), Value::Object(right_object)) => {
+                    let object_context = property_filter.map(|_| {
+                        let mut object_context = self.new_child(left, right);
+                        object_context.path = path.clone();
+                        object_context
+                    });
+                    let child_path =
+                        |key: &str| property_filter.map(|_| PathSegment::new(path.clone(), key));
+                    let is_compared = |key: &str| match (property_filter, &object_context) {
+                        (Some(property_filter), Some(object_context)) => {
+                            property_filter(key, object_context)
+                        }
+                        _ => true,
+                    };
+
+                    for (key, left_value) in left_object.iter().filter(|(key, _)| is_compared(key))
+                    {
+                        match right_object.get(key) {
+                            Some(right_value) => {
+                                pending.push((left_value, right_value, child_path(key)))
+                            }
+                            None => return false,
+                        }
+                    }
+                    if right_object
+                        .keys()
+                        .any(|key| !left_object.contains_key(key) && is_compared(key))
+                    {
+                        return false;
+                    }
+                }
+                (Value::Array(left_array), Value::Array(right_array)) => {
+                    if left_array.len() != right_array.len() {
+                        return false;
+                    }
+                    for (index, (left_item, right_item)) in
+                        left_array.iter().zip(right_array).enumerate()
+                    {
+                        let item_path = property_filter
+                            .map(|_| PathSegment::new(path.clone(), &index.to_string()));
+                        pending.push((left_item, right_item, item_path));
+                    }
+                }
+                (Value::Object(_) | Value::Array(_), _)
+                | (_, Value::Object(_) | Value::Array(_)) => {
+                    return false;
+                }
+                _ => {
+                    if left != right {
+                        return false;
+                    }
+                }
+            }
         }
+        true
     }
 }
@@ -5,7 +5,8 @@ pub mod reverse;
 pub use diff::DiffContext;
 pub use patch::PatchContext;
 
-use crate::types::{Options, OPTIONS};
+use crate::types::Options;
+use std::sync::Arc;
 
 /// A trait that defines the interface for filter contexts.
 ///
@@ -55,27 +56,34 @@ where
     }
 
     fn options(&self) -> &Options {
-        OPTIONS.get().expect("options not set")
+        &self.inner_data().options
     }
 
     fn inner_data(&self) -> &ContextData<Self>;
     fn inner_data_mut(&mut self) -> &mut ContextData<Self>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ContextData<FC: FilterContext> {
     result: Option<FC::Result>,
     exiting: bool,
+    options: Arc<Options>,
 }
 
 impl<FC: FilterContext> ContextData<FC> {
-    pub fn new() -> Self {
+    pub fn new(options: Arc<Options>) -> Self {
         Self {
             result: None,
             exiting: false,
+            options,
         }
     }
 
+    /// The options shared by this context and the children created from it
+    pub fn shared_options(&self) -> &Arc<Options> {
+        &self.options
+    }
+
     pub fn is_exiting(&self) -> bool {
         self.exiting
     }
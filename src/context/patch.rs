@@ -1,12 +1,14 @@
 use crate::context::{ContextData, FilterContext};
-use crate::types::Delta;
+use crate::types::{Delta, Options};
 use serde_json::Value;
 use std::borrow::Cow;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct PatchContext<'a> {
     context_data: ContextData<Self>,
-    pub left: &'a Value,
+    /// The value to patch, `None` when patching an object property that is missing
+    pub left: Option<&'a Value>,
     delta: DeltaWithLeftover<'a>,
 }
 
@@ -20,6 +22,8 @@ pub enum DeltaIndicator {
     Modified,
     Deleted,
     Moved,
+    Unordered,
+    Keyed,
     None,
 }
 
@@ -33,6 +37,8 @@ impl DeltaIndicator {
             Delta::Modified(_, _) => DeltaIndicator::Modified,
             Delta::Deleted(_) => DeltaIndicator::Deleted,
             Delta::Moved { .. } => DeltaIndicator::Moved,
+            Delta::Unordered { .. } => DeltaIndicator::Unordered,
+            Delta::Keyed { .. } => DeltaIndicator::Keyed,
             Delta::None => DeltaIndicator::None,
         }
     }
@@ -54,11 +60,25 @@ impl<'a> DeltaWithLeftover<'a> {
 }
 
 impl<'a> PatchContext<'a> {
-    pub fn new(left: &'a Value, delta: Delta<'a>) -> Self {
+    pub fn new(left: &'a Value, delta: Delta<'a>, options: Arc<Options>) -> Self {
+        Self {
+            left: Some(left),
+            delta: DeltaWithLeftover::Delta(delta),
+            context_data: ContextData::new(options),
+        }
+    }
+
+    /// Creates a context for a nested value and its delta, sharing the options of this context
+    pub fn new_child(&self, left: &'a Value, delta: Delta<'a>) -> Self {
+        self.new_property_child(Some(left), delta)
+    }
+
+    /// Creates a context for an object property, which may be missing from the patched object
+    pub fn new_property_child(&self, left: Option<&'a Value>, delta: Delta<'a>) -> Self {
         Self {
             left,
             delta: DeltaWithLeftover::Delta(delta),
-            context_data: ContextData::new(),
+            context_data: ContextData::new(Arc::clone(self.context_data.shared_options())),
         }
     }
 
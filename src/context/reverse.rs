@@ -1,5 +1,6 @@
 use crate::context::{ContextData, FilterContext};
-use crate::types::Delta;
+use crate::types::{Delta, Options};
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct ReverseContext<'a> {
@@ -21,11 +22,16 @@ impl<'a> FilterContext for ReverseContext<'a> {
 }
 
 impl<'a> ReverseContext<'a> {
-    pub fn new(delta: Delta<'a>) -> Self {
+    pub fn new(delta: Delta<'a>, options: Arc<Options>) -> Self {
         Self {
             delta,
-            context_data: ContextData::new(),
+            context_data: ContextData::new(options),
             new_name: None,
         }
     }
+
+    /// Creates a context for a nested delta, sharing the options of this context
+    pub fn new_child(&self, delta: Delta<'a>) -> Self {
+        Self::new(delta, Arc::clone(self.context_data.shared_options()))
+    }
 }
@@ -1,9 +1,13 @@
+use crate::context::reverse::ReverseContext;
 use crate::context::{DiffContext, FilterContext, PatchContext};
+use crate::errors::JsonDiffPatchError;
 use crate::pipeline::diff_pipeline::DiffPipeline;
 use crate::pipeline::patch_pipeline::PatchPipeline;
+use crate::pipeline::reverse_pipeline::ReversePipeline;
 use crate::processor::Processor;
 use crate::types::{Delta, Options};
 use serde_json::Value;
+use std::sync::Arc;
 
 pub struct DiffPatcher {
     processor: Processor,
@@ -16,42 +20,317 @@ impl DiffPatcher {
         }
     }
 
+    pub fn options(&self) -> &Options {
+        self.processor.options()
+    }
+
     pub fn diff<'a>(&self, left: &'a Value, right: &'a Value) -> Option<Delta<'a>> {
-        // Create a diff context
+        self.try_diff(left, right).expect("diff failed")
+    }
 
-        let mut context = DiffContext::new(left, right);
+    /// Same as [`DiffPatcher::diff`], but returns an error instead of panicking
+    pub fn try_diff<'a>(
+        &self,
+        left: &'a Value,
+        right: &'a Value,
+    ) -> Result<Option<Delta<'a>>, JsonDiffPatchError> {
+        let mut context = DiffContext::new(left, right, Arc::clone(self.processor.options()));
         let mut diff_pipe = DiffPipeline;
-        self.processor
-            .process(&mut context, &mut diff_pipe)
-            .expect("diff failed");
+        self.processor.process(&mut context, &mut diff_pipe)?;
+
+        Ok(context.pop_result())
+    }
 
-        context.get_result().cloned()
+    /// Same as [`DiffPatcher::diff`], but the delta owns its values and can outlive `left` and
+    /// `right`. The values are copied once the delta is found, or while diffing with
+    /// `clone_diff_values`, in which case they are only moved here.
+    pub fn diff_owned(&self, left: &Value, right: &Value) -> Option<Delta<'static>> {
+        self.diff(left, right).map(Delta::into_owned)
     }
 
-    pub fn patch(&self, _left: &Value, delta: Delta) -> Option<Value> {
-        // Create a patch context
-        // For now, return None as the implementation is simplified
-        let mut context = PatchContext::new(_left, delta);
+    pub fn patch(&self, left: &Value, delta: Delta) -> Option<Value> {
+        self.try_patch(left, delta).expect("patch failed")
+    }
+
+    /// Same as [`DiffPatcher::patch`], but returns an error instead of panicking, e.g. when the
+    /// delta does not fit the given value
+    pub fn try_patch(
+        &self,
+        left: &Value,
+        delta: Delta,
+    ) -> Result<Option<Value>, JsonDiffPatchError> {
+        let mut context = PatchContext::new(left, delta, Arc::clone(self.processor.options()));
         let mut patch_pipe = PatchPipeline;
-        self.processor
-            .process(&mut context, &mut patch_pipe)
-            .expect("patch failed");
+        self.processor.process(&mut context, &mut patch_pipe)?;
 
-        context.pop_result().map(|r| r.into_owned())
+        Ok(context.pop_result().map(|r| r.into_owned()))
     }
 
-    pub fn reverse(&self, _delta: &Delta) -> Option<Delta<'_>> {
-        // Create a reverse context
-        // For now, return None as the implementation is simplified
-        None
+    pub fn reverse<'a>(&self, delta: &Delta<'a>) -> Option<Delta<'a>> {
+        self.try_reverse(delta).expect("reverse failed")
+    }
+
+    /// Same as [`DiffPatcher::reverse`], but returns an error instead of panicking
+    pub fn try_reverse<'a>(
+        &self,
+        delta: &Delta<'a>,
+    ) -> Result<Option<Delta<'a>>, JsonDiffPatchError> {
+        let mut context = ReverseContext::new(delta.clone(), Arc::clone(self.processor.options()));
+        let mut reverse_pipe = ReversePipeline;
+        self.processor.process(&mut context, &mut reverse_pipe)?;
+
+        match context.pop_result() {
+            Some(Delta::None) => Ok(None),
+            result => Ok(result),
+        }
     }
 
     pub fn unpatch(&self, right: &Value, delta: &Delta) -> Option<Value> {
+        self.try_unpatch(right, delta).expect("unpatch failed")
+    }
+
+    /// Same as [`DiffPatcher::unpatch`], but returns an error instead of panicking
+    pub fn try_unpatch(
+        &self,
+        right: &Value,
+        delta: &Delta,
+    ) -> Result<Option<Value>, JsonDiffPatchError> {
         // Unpatch is patch with reversed delta
-        if let Some(reversed_delta) = self.reverse(delta) {
-            self.patch(right, reversed_delta)
-        } else {
-            None
+        match self.try_reverse(delta)? {
+            Some(reversed_delta) => self.try_patch(right, reversed_delta),
+            None => Ok(None),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ArrayOptions;
+    use serde_json::json;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_instances_keep_their_own_options() {
+        let without_moves = DiffPatcher::new(Some(Options {
+            arrays: Some(ArrayOptions {
+                detect_move: Some(false),
+                include_value_on_move: Some(false),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let with_moves = DiffPatcher::new(None);
+        // the lazily created default instance must not clash with the configured ones
+        assert!(crate::diff(&json!(1), &json!(2)).is_some());
+
+        let left = json!([1, 2, 3]);
+        let right = json!([2, 3, 1]);
+
+        assert_eq!(
+            serde_json::to_value(with_moves.diff(&left, &right).unwrap()).unwrap(),
+            json!({"_t": "a", "_0": ["", 2, 3]})
+        );
+        assert_eq!(
+            serde_json::to_value(without_moves.diff(&left, &right).unwrap()).unwrap(),
+            json!({"_t": "a", "_0": [1, null, 0], "2": [1]})
+        );
+        assert_eq!(
+            without_moves.options().arrays.as_ref().unwrap().detect_move,
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn test_try_patch_reports_invalid_deltas() {
+        let diffpatcher = DiffPatcher::new(None);
+        let x = json!("x");
+
+        let err = diffpatcher
+            .try_patch(&json!({"a": 1}), Delta::Array(vec![]))
+            .unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::InvalidPatchToTarget { .. }),
+            "{:?}",
+            err
+        );
+
+        let err = diffpatcher
+            .try_patch(
+                &json!([1, 2]),
+                Delta::Array(vec![(
+                    crate::types::ArrayDeltaIndex::RemovedOrMoved(5),
+                    Delta::deleted_ref(&x),
+                )]),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                JsonDiffPatchError::IndexOutOfBoundsRemove {
+                    index: 5,
+                    length: 2
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let err = diffpatcher
+            .try_patch(
+                &json!([1, 2]),
+                Delta::Array(vec![(
+                    crate::types::ArrayDeltaIndex::NewOrModified(7),
+                    Delta::modified_ref(&x, &x),
+                )]),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                JsonDiffPatchError::IndexOutOfBoundsModify {
+                    index: 7,
+                    length: 2
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        let err = diffpatcher
+            .try_patch(
+                &json!(1),
+                Delta::TextDiff("@@ -1 +1 @@\n-a\n+b\n".to_string()),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::InvalidPatchToTarget { .. }),
+            "{:?}",
+            err
+        );
+
+        let err = diffpatcher
+            .try_reverse(&Delta::Moved {
+                moved_value: None,
+                new_index: 1,
+            })
+            .unwrap_err();
+        assert!(matches!(err, JsonDiffPatchError::Reverse(_)), "{:?}", err);
+
+        assert_eq!(
+            crate::try_patch(&json!({"a": 1}), Delta::Object(Default::default())).unwrap(),
+            Some(json!({"a": 1}))
+        );
+    }
+
+    #[test]
+    fn test_omit_removed_values() {
+        let compact = DiffPatcher::new(Some(Options {
+            omit_removed_values: Some(true),
+            ..Default::default()
+        }));
+
+        let left = json!({"big": {"nested": [1, 2, 3]}, "n": 1, "list": [{"a": 1}, 2, 3]});
+        let right = json!({"n": 2, "list": [2, 3, 4]});
+        let delta = compact.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            json!({
+                "big": [0, 0, 4],
+                "n": [0, 2, 5],
+                "list": {"_t": "a", "_0": [0, 0, 4], "2": [4]}
+            })
+        );
+
+        assert_eq!(
+            compact.try_patch(&left, delta.clone()).unwrap(),
+            Some(right.clone())
+        );
+        let err = compact.try_unpatch(&right, &delta).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                JsonDiffPatchError::Reverse(
+                    crate::errors::JsonDiffPatchReverseError::MissingRemovedValue
+                )
+            ),
+            "{:?}",
+            err
+        );
+
+        // the omitted values are still missing once the delta is stored and read back
+        let stored: Delta = serde_json::to_string(&delta).unwrap().parse().unwrap();
+        assert_eq!(
+            compact.try_patch(&left, stored.clone()).unwrap(),
+            Some(right.clone())
+        );
+        assert!(matches!(
+            compact.try_unpatch(&right, &stored),
+            Err(JsonDiffPatchError::Reverse(
+                crate::errors::JsonDiffPatchReverseError::MissingRemovedValue
+            ))
+        ));
+        // actual values written like the placeholders are kept
+        let left = json!({"a": 0, "b": 0, "c": "__omitted__"});
+        let right = json!({"b": 1});
+        let delta = crate::diff(&left, &right).unwrap();
+        let stored: Delta = serde_json::to_string(&delta).unwrap().parse().unwrap();
+        assert_eq!(crate::unpatch(&right, &stored), Some(left));
+
+        let stored: Delta = r#"{"a": [0, 0, 4]}"#.parse().unwrap();
+        assert!(matches!(
+            crate::try_unpatch(&json!({}), &stored),
+            Err(JsonDiffPatchError::Reverse(
+                crate::errors::JsonDiffPatchReverseError::MissingRemovedValue
+            ))
+        ));
+        assert!(matches!(
+            crate::try_reverse(&stored),
+            Err(JsonDiffPatchError::Reverse(
+                crate::errors::JsonDiffPatchReverseError::MissingRemovedValue
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_owned_delta_outlives_documents() {
+        let cloning = DiffPatcher::new(Some(Options {
+            clone_diff_values: Some(true),
+            ..Default::default()
+        }));
+
+        let (delta, owned_delta) = {
+            let left = json!({"a": 1, "b": [1, 2, {"c": "d"}], "e": "f"});
+            let right = json!({"a": 2, "b": [2, {"c": "d"}, 3]});
+            let delta = cloning.diff(&left, &right).unwrap();
+            let Delta::Object(changes) = &delta else {
+                panic!("expected an object delta, got {:?}", delta);
+            };
+            assert!(matches!(
+                &changes["a"],
+                Delta::Modified(Some(Cow::Owned(_)), Cow::Owned(_))
+            ));
+            (
+                delta.into_owned(),
+                DiffPatcher::new(None).diff_owned(&left, &right).unwrap(),
+            )
+        };
+
+        // deltas can be moved to other threads once the documents are gone
+        let handle = std::thread::spawn(move || {
+            (
+                serde_json::to_value(&delta).unwrap(),
+                serde_json::to_value(&owned_delta).unwrap(),
+            )
+        });
+        let (serialized, owned_serialized) = handle.join().unwrap();
+        assert_eq!(serialized, owned_serialized);
+        assert_eq!(
+            crate::patch(
+                &json!({"a": 1, "b": [1, 2, {"c": "d"}], "e": "f"}),
+                Delta::from_value(serialized).unwrap()
+            ),
+            Some(json!({"a": 2, "b": [2, {"c": "d"}, 3]}))
+        );
+    }
+}
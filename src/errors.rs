@@ -1,4 +1,5 @@
 use diff_match_patch_rs::Error as DiffMatchPatchError;
+use serde_json::Value;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -25,8 +26,26 @@ pub enum JsonDiffPatchError {
     #[error("index out of bounds: the patch is trying to modify an item at index {index}, but the array has only {length} items")]
     IndexOutOfBoundsModify { index: usize, length: usize },
 
+    #[error("the patch is trying to remove {item} from an unordered array, but the array does not contain it")]
+    UnorderedItemNotFound { item: Value },
+
+    #[error("the patch is trying to change the item with {key_field} {key}, but the array does not contain it")]
+    KeyedItemNotFound { key_field: String, key: String },
+
+    #[error("the patch is trying to add an item with {key_field} {key}, but the array already contains one")]
+    KeyedItemExists { key_field: String, key: String },
+
+    #[error("maximum nesting depth of {max_depth} exceeded")]
+    MaxDepthExceeded { max_depth: usize },
+
     #[error("failed to apply text diff: {0:#?}")]
     ApplyTextDiffFailed(DiffMatchPatchError),
+
+    #[error(transparent)]
+    Reverse(#[from] JsonDiffPatchReverseError),
+
+    #[error(transparent)]
+    JsonPatch(#[from] JsonPatchError),
 }
 
 impl From<DiffMatchPatchError> for JsonDiffPatchError {
@@ -39,4 +58,88 @@ impl From<DiffMatchPatchError> for JsonDiffPatchError {
 pub enum JsonDiffPatchReverseError {
     #[error("The given move delta cannot be reversed. Needs to be handled by the higher level array directly.")]
     InvalidMoveDelta,
+
+    #[error("The given array delta cannot be reversed: {0}")]
+    InvalidArrayDelta(String),
+
+    #[error("The given delta cannot be reversed, as it was created without the removed values (omit_removed_values)")]
+    MissingRemovedValue,
+}
+
+/// Errors of JSON Patch (RFC 6902) operations, along with the position of the failing operation
+#[derive(Error, Debug)]
+pub enum JsonPatchError {
+    #[error("JSON Patch operation {index}: '{pointer}' is not a valid JSON pointer")]
+    InvalidPointer { index: usize, pointer: String },
+
+    #[error("JSON Patch operation {index}: there is no value at '{pointer}'")]
+    PathNotFound { index: usize, pointer: String },
+
+    #[error(
+        "JSON Patch operation {index}: test of '{path}' failed, expected {expected}, found {found}"
+    )]
+    TestFailed {
+        index: usize,
+        path: String,
+        expected: Value,
+        found: Value,
+    },
+
+    #[error("JSON Patch operation {index}: cannot move '{from}' into its own child '{path}'")]
+    MoveIntoChild {
+        index: usize,
+        from: String,
+        path: String,
+    },
+
+    #[error("JSON Patch operation {index}: the whole document cannot be removed")]
+    RemoveRoot { index: usize },
+}
+
+#[derive(Error, Debug)]
+pub enum JsonDiffPatchDeserializeError {
+    #[error("invalid delta at '{path}': expected a JSON array or object, found {found}")]
+    UnexpectedType { path: String, found: Value },
+
+    #[error("invalid delta at '{path}': expected an array of 1, 2 or 3 elements, found {length}")]
+    InvalidLength { path: String, length: usize },
+
+    #[error("invalid delta at '{path}': unknown magic number {magic}")]
+    UnknownMagicNumber { path: String, magic: Value },
+
+    #[error(
+        "invalid delta at '{path}': expected 0 or null as the placeholder element, found {found}"
+    )]
+    InvalidPlaceholder { path: String, found: Value },
+
+    #[error("invalid delta at '{path}': text diff must be a string, found {found}")]
+    InvalidTextDiff { path: String, found: Value },
+
+    #[error(
+        "invalid delta at '{path}': move target must be a non-negative integer, found {found}"
+    )]
+    InvalidMoveIndex { path: String, found: Value },
+
+    #[error(
+        "invalid delta at '{path}': array type marker must be \"a\", \"u\" or \"k\", found {found}"
+    )]
+    InvalidArrayMarker { path: String, found: Value },
+
+    #[error("invalid delta at '{path}': '{key}' is not a valid array delta index")]
+    InvalidArrayIndex { path: String, key: String },
+
+    #[error("invalid delta at '{path}': unordered array items must be listed in an array, found {found}")]
+    InvalidUnorderedItems { path: String, found: Value },
+
+    #[error("invalid delta at '{path}': unexpected key '{key}' in an unordered array delta")]
+    InvalidUnorderedKey { path: String, key: String },
+
+    #[error("invalid delta at '{path}': keyed array delta needs a \"_key\" string, found {found}")]
+    InvalidKeyField { path: String, found: Value },
+
+    #[error("invalid delta at '{path}': the order of a keyed array delta must be two arrays of keys, found {found}")]
+    InvalidKeyedOrder { path: String, found: Value },
+
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
 }
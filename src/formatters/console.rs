@@ -0,0 +1,457 @@
+//! Formats deltas as a tree for the console, showing the changes alongside the unchanged values
+//! around them: additions in green, deletions in red, and modifications as `old => new`.
+
+use crate::errors::JsonDiffPatchError;
+use crate::formatters::tree::{self, ContainerKind, Entry, Node};
+use crate::types::Delta;
+use diff_match_patch_rs::Ops;
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct ConsoleFormatter {
+    /// Colors the changes with ANSI escape codes
+    pub color: bool,
+    /// Number of unchanged properties or items shown around each change, all of them are shown
+    /// when `None`
+    pub context_lines: Option<usize>,
+}
+
+impl Default for ConsoleFormatter {
+    fn default() -> Self {
+        Self {
+            color: true,
+            context_lines: Some(3),
+        }
+    }
+}
+
+/// Formats a delta with the default options, see [`ConsoleFormatter::format`]
+pub fn format(left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+    ConsoleFormatter::default().format(left, delta)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Style {
+    Added,
+    Deleted,
+    Moved,
+    Context,
+}
+
+/// A line of the output, or a delta still to be formatted into lines
+enum Work<'d, 'a> {
+    Line(String),
+    Node {
+        label: Option<String>,
+        depth: usize,
+        left: Option<&'d Value>,
+        delta: &'d Delta<'a>,
+    },
+}
+
+impl ConsoleFormatter {
+    /// Formats the delta as it applies to `left`, which provides the unchanged values and the old
+    /// values of the changes
+    pub fn format(&self, left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+        let mut output = String::new();
+        let mut pending = vec![Work::Node {
+            label: None,
+            depth: 0,
+            left: Some(left),
+            delta,
+        }];
+        while let Some(work) = pending.pop() {
+            match work {
+                Work::Line(line) => {
+                    output.push_str(&line);
+                    output.push('\n');
+                }
+                Work::Node {
+                    label,
+                    depth,
+                    left,
+                    delta,
+                } => {
+                    let mut children = Vec::new();
+                    self.format_node(
+                        label.as_deref(),
+                        depth,
+                        tree::node(left, delta)?,
+                        &mut children,
+                    );
+                    pending.extend(children.into_iter().rev());
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Formats the node of a value, as lines and nested deltas pushed to `output` in order
+    fn format_node<'d, 'a>(
+        &self,
+        label: Option<&str>,
+        depth: usize,
+        node: Node<'d, 'a>,
+        output: &mut Vec<Work<'d, 'a>>,
+    ) {
+        let line = match node {
+            Node::Added(value) => self.value_line(Style::Added, depth, label, value),
+            Node::Deleted(value) => self.value_line(Style::Deleted, depth, label, value),
+            Node::Modified(old_value, new_value) => {
+                let content = format!(
+                    "{} => {}",
+                    self.paint(Style::Deleted, &old_value.to_string()),
+                    self.paint(Style::Added, &new_value.to_string())
+                );
+                self.line(None, depth, label, &content)
+            }
+            Node::TextDiff(chunks) => self.line(None, depth, label, &self.text_diff(chunks)),
+            Node::Container { kind, entries } => {
+                self.container(label, depth, kind, entries, output);
+                return;
+            }
+            Node::None => return,
+        };
+        output.push(Work::Line(line));
+    }
+
+    /// Pushes the lines of a container: its opening line, the entries with the unchanged ones
+    /// away from the changes collapsed, and its closing line
+    fn container<'d, 'a>(
+        &self,
+        label: Option<&str>,
+        depth: usize,
+        kind: ContainerKind,
+        entries: Vec<Entry<'d, 'a>>,
+        output: &mut Vec<Work<'d, 'a>>,
+    ) {
+        let (open, close) = match kind {
+            ContainerKind::Object => ("{", "}"),
+            ContainerKind::Array | ContainerKind::Keyed => ("[", "]"),
+            ContainerKind::Unordered => ("[", "] (unordered)"),
+        };
+        output.push(Work::Line(self.line(None, depth, label, open)));
+
+        let distances = tree::change_distances(&entries);
+        let depth = depth + 1;
+        let mut collapsed = false;
+        for (entry, distance) in entries.into_iter().zip(distances) {
+            if self
+                .context_lines
+                .is_some_and(|context_lines| distance > context_lines)
+            {
+                if !collapsed {
+                    output.push(Work::Line(self.line(None, depth, None, "...")));
+                }
+                collapsed = true;
+                continue;
+            }
+            collapsed = false;
+
+            let work = match entry {
+                Entry::Unchanged { label, value } => Work::Line(self.line(
+                    None,
+                    depth,
+                    label.as_deref(),
+                    &self.paint(Style::Context, &value.to_string()),
+                )),
+                Entry::Added { label, value } => {
+                    Work::Line(self.value_line(Style::Added, depth, label.as_deref(), value))
+                }
+                Entry::Deleted { label, value } => {
+                    Work::Line(self.value_line(Style::Deleted, depth, label.as_deref(), value))
+                }
+                Entry::Moved {
+                    label,
+                    value,
+                    new_index,
+                } => {
+                    let content = format!("{} (moved to {})", value, new_index);
+                    Work::Line(self.line(
+                        None,
+                        depth,
+                        Some(&label),
+                        &self.paint(Style::Moved, &content),
+                    ))
+                }
+                Entry::Changed { label, left, delta } => Work::Node {
+                    label,
+                    depth,
+                    left,
+                    delta,
+                },
+            };
+            output.push(work);
+        }
+
+        output.push(Work::Line(self.line(None, depth - 1, None, close)));
+    }
+
+    /// A line of an added or deleted value
+    fn value_line(&self, style: Style, depth: usize, label: Option<&str>, value: &Value) -> String {
+        self.line(
+            Some(style),
+            depth,
+            label,
+            &self.paint(style, &value.to_string()),
+        )
+    }
+
+    /// A line of the output, where additions and deletions are marked in the margin
+    fn line(
+        &self,
+        mark: Option<Style>,
+        depth: usize,
+        label: Option<&str>,
+        content: &str,
+    ) -> String {
+        let margin = match mark {
+            Some(Style::Added) => self.paint(Style::Added, "+"),
+            Some(Style::Deleted) => self.paint(Style::Deleted, "-"),
+            _ => " ".to_string(),
+        };
+        let indent = "  ".repeat(depth);
+        match label {
+            Some(label) => {
+                let label = match mark {
+                    Some(style) => self.paint(style, label),
+                    None => label.to_string(),
+                };
+                format!("{} {}{}: {}", margin, indent, label, content)
+            }
+            None => format!("{} {}{}", margin, indent, content),
+        }
+    }
+
+    /// The new text, with the removed parts of the old text inline
+    fn text_diff(&self, chunks: Vec<(Ops, String)>) -> String {
+        let mut content = String::from("\"");
+        for (op, text) in chunks {
+            // escaped as in a JSON string
+            let text = Value::String(text).to_string();
+            let text = &text[1..text.len() - 1];
+            match op {
+                Ops::Equal => content.push_str(text),
+                Ops::Delete if self.color => content.push_str(&self.paint(Style::Deleted, text)),
+                Ops::Insert if self.color => content.push_str(&self.paint(Style::Added, text)),
+                Ops::Delete => content.push_str(&format!("[-{}-]", text)),
+                Ops::Insert => content.push_str(&format!("{{+{}+}}", text)),
+            }
+        }
+        content.push('"');
+        content
+    }
+
+    fn paint(&self, style: Style, text: &str) -> String {
+        if !self.color {
+            return text.to_string();
+        }
+        let code = match style {
+            Style::Added => "32",
+            Style::Deleted => "31",
+            Style::Moved => "33",
+            Style::Context => "90",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+    use crate::diffpatcher::DiffPatcher;
+    use crate::types::{ArrayDeltaIndex, ArrayOptions, Options};
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn plain(context_lines: Option<usize>) -> ConsoleFormatter {
+        ConsoleFormatter {
+            color: false,
+            context_lines,
+        }
+    }
+
+    #[test]
+    fn test_format_object() {
+        let left = json!({"a": 1, "b": {"c": "x", "d": [1]}, "e": true, "f": null});
+        let right = json!({"a": 2, "b": {"c": "x", "g": 3}, "e": true});
+        let delta = diff(&left, &right).unwrap();
+
+        assert_eq!(
+            plain(None).format(&left, &delta).unwrap(),
+            [
+                "  {",
+                "    a: 1 => 2",
+                "    b: {",
+                "      c: \"x\"",
+                "-     d: [1]",
+                "+     g: 3",
+                "    }",
+                "    e: true",
+                "-   f: null",
+                "  }",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_format_array() {
+        let left = json!([0, 1, 2, 3, 4, 5, 6, {"a": 1}, 8, 9, 10]);
+        let right = json!([0, 1, 2, 3, 4, 5, 6, {"a": 2}, 8, 10, "new"]);
+        let delta = diff(&left, &right).unwrap();
+
+        assert_eq!(
+            plain(Some(1)).format(&left, &delta).unwrap(),
+            [
+                "  [",
+                "    ...",
+                "    6: 6",
+                "-   7: {\"a\":1}",
+                "+   7: {\"a\":2}",
+                "    8: 8",
+                "-   9: 9",
+                "    9: 10",
+                "+   10: \"new\"",
+                "  ]",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let left = json!([1, 2, 3, 4, 5]);
+        let right = json!([2, 3, 4, 5, 1]);
+        let delta = diff(&left, &right).unwrap();
+        assert_eq!(
+            plain(Some(0)).format(&left, &delta).unwrap(),
+            ["  [", "    0: 1 (moved to 4)", "    ...", "  ]", ""].join("\n")
+        );
+
+        let left = json!([{"a": 1}, 2]);
+        let (old_value, new_value) = (json!(1), json!(3));
+        let delta = Delta::Array(vec![(
+            ArrayDeltaIndex::NewOrModified(0),
+            Delta::Object(HashMap::from([(
+                "a".to_string(),
+                Delta::modified_ref(&old_value, &new_value),
+            )])),
+        )]);
+        assert_eq!(
+            plain(Some(0)).format(&left, &delta).unwrap(),
+            [
+                "  [",
+                "    0: {",
+                "      a: 1 => 3",
+                "    }",
+                "    ...",
+                "  ]",
+                ""
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_format_unordered_and_keyed_arrays() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            arrays: Some(ArrayOptions {
+                unordered_paths: Some(vec!["/tags".to_string()]),
+                keyed_paths: Some([("/items".to_string(), "name".to_string())].into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let left = json!({
+            "items": [{"name": "x", "v": 1}, {"name": "y", "v": 2}],
+            "tags": ["a", "b", "a"]
+        });
+        let right = json!({
+            "items": [{"name": "w"}, {"name": "x", "v": 3}],
+            "tags": ["a", "c", "a"]
+        });
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+
+        assert_eq!(
+            plain(None).format(&left, &delta).unwrap(),
+            [
+                "  {",
+                "    items: [",
+                "      name=x: {",
+                "        name: \"x\"",
+                "        v: 1 => 3",
+                "      }",
+                "-     name=y: {\"name\":\"y\",\"v\":2}",
+                "+     name=w: {\"name\":\"w\"}",
+                "    ]",
+                "    tags: [",
+                "-     \"b\"",
+                "+     \"c\"",
+                "    ] (unordered)",
+                "  }",
+                "",
+            ]
+            .join("\n")
+        );
+
+        // reordered items of keyed arrays are shown as moved
+        let reordered =
+            json!({"items": [{"name": "y", "v": 2}, {"name": "x", "v": 1}], "tags": []});
+        let delta = diffpatcher.diff(&left, &reordered).unwrap();
+        assert_eq!(
+            plain(None).format(&left, &delta).unwrap(),
+            [
+                "  {",
+                "    items: [",
+                "      name=x: {\"name\":\"x\",\"v\":1} (moved to 1)",
+                "      name=y: {\"name\":\"y\",\"v\":2} (moved to 0)",
+                "    ]",
+                "    tags: [",
+                "-     \"a\"",
+                "-     \"b\"",
+                "-     \"a\"",
+                "    ] (unordered)",
+                "  }",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_format_text_diff() {
+        let left = json!({"text": "The quick brown fox jumps over the lazy dog, again and again and again."});
+        let right = json!({"text": "The quick red fox jumps over the lazy dog, again and again and again."});
+        let delta = diff(&left, &right).unwrap();
+        assert!(
+            matches!(&delta, Delta::Object(changes) if matches!(changes["text"], Delta::TextDiff(_)))
+        );
+
+        assert_eq!(
+            plain(None).format(&left, &delta).unwrap(),
+            [
+                "  {",
+                "    text: \"The quick [-b-]r[-own-]{+ed+} fox jumps over the lazy dog, again and again and again.\"",
+                "  }",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_format_colors() {
+        let left = json!({"a": 1, "b": 2});
+        let right = json!({"a": 1, "c": 3});
+        let delta = diff(&left, &right).unwrap();
+
+        let output = format(&left, &delta).unwrap();
+        assert!(output.contains("    a: \x1b[90m1\x1b[0m\n"));
+        assert!(output.contains("\x1b[31m-\x1b[0m   \x1b[31mb\x1b[0m: \x1b[31m2\x1b[0m\n"));
+        assert!(output.contains("\x1b[32m+\x1b[0m   \x1b[32mc\x1b[0m: \x1b[32m3\x1b[0m\n"));
+        assert!(!plain(None).format(&left, &delta).unwrap().contains('\x1b'));
+
+        assert!(format(&json!(1), &Delta::Array(Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,346 @@
+//! Formats deltas as self-contained HTML, showing the changes alongside the unchanged values
+//! around them, with the unchanged values away from the changes collapsed in `<details>`
+//! elements.
+//!
+//! The markup only uses `jsondiffpatch-*` classes, styled by [`STYLE`], which [`format_page`]
+//! embeds in a standalone page.
+
+use crate::errors::JsonDiffPatchError;
+use crate::formatters::tree::{self, ContainerKind, Entry, Node};
+use crate::types::Delta;
+use diff_match_patch_rs::Ops;
+use serde_json::Value;
+
+/// Style sheet of the markup, to include in the pages showing formatted deltas
+pub const STYLE: &str = r#".jsondiffpatch-delta { font-family: monospace; font-size: 12px; }
+.jsondiffpatch-delta ul { list-style: none; margin: 0; padding: 0 0 0 20px; }
+.jsondiffpatch-delta code { white-space: pre-wrap; }
+.jsondiffpatch-property-name { font-weight: bold; }
+.jsondiffpatch-property-name::after { content: ": "; }
+.jsondiffpatch-unchanged, .jsondiffpatch-collapsed summary { color: #888; }
+.jsondiffpatch-added > code, .jsondiffpatch-delta ins { background: #bbffbb; text-decoration: none; }
+.jsondiffpatch-deleted > code, .jsondiffpatch-delta del { background: #ffbbbb; text-decoration: line-through; }
+.jsondiffpatch-moved-destination { color: #bb8800; margin-left: 6px; }
+.jsondiffpatch-collapsed summary { cursor: pointer; }
+"#;
+
+#[derive(Debug, Clone)]
+pub struct HtmlFormatter {
+    /// Number of unchanged properties or items shown around each change, the others are
+    /// collapsed; all of them are shown when `None`
+    pub context_lines: Option<usize>,
+}
+
+impl Default for HtmlFormatter {
+    fn default() -> Self {
+        Self {
+            context_lines: Some(3),
+        }
+    }
+}
+
+/// Formats a delta as an HTML fragment with the default options, see [`HtmlFormatter::format`]
+pub fn format(left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+    HtmlFormatter::default().format(left, delta)
+}
+
+/// Formats a delta as an HTML page with the default options, see
+/// [`HtmlFormatter::format_page`]
+pub fn format_page(left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+    HtmlFormatter::default().format_page(left, delta)
+}
+
+/// Markup of the output, or a delta still to be formatted into markup
+enum Work<'d, 'a> {
+    Markup(String),
+    Node {
+        label: Option<String>,
+        left: Option<&'d Value>,
+        delta: &'d Delta<'a>,
+    },
+}
+
+impl HtmlFormatter {
+    /// Formats the delta as it applies to `left`, which provides the unchanged values and the old
+    /// values of the changes, as an HTML fragment to be styled with [`STYLE`]
+    pub fn format(&self, left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+        let mut output = String::from("<div class=\"jsondiffpatch-delta\">\n<ul>\n");
+        let mut pending = vec![Work::Node {
+            label: None,
+            left: Some(left),
+            delta,
+        }];
+        while let Some(work) = pending.pop() {
+            match work {
+                Work::Markup(markup) => {
+                    output.push_str(&markup);
+                    output.push('\n');
+                }
+                Work::Node { label, left, delta } => {
+                    let mut children = Vec::new();
+                    self.format_node(label.as_deref(), tree::node(left, delta)?, &mut children);
+                    pending.extend(children.into_iter().rev());
+                }
+            }
+        }
+        output.push_str("</ul>\n</div>\n");
+        Ok(output)
+    }
+
+    /// Formats the delta as a standalone HTML page, embedding its style sheet
+    pub fn format_page(&self, left: &Value, delta: &Delta) -> Result<String, JsonDiffPatchError> {
+        Ok(format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Delta</title>\n\
+             <style>\n{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+            STYLE,
+            self.format(left, delta)?
+        ))
+    }
+
+    /// Formats the node of a value, as markup and nested deltas pushed to `output` in order
+    fn format_node<'d, 'a>(
+        &self,
+        label: Option<&str>,
+        node: Node<'d, 'a>,
+        output: &mut Vec<Work<'d, 'a>>,
+    ) {
+        let item = match node {
+            Node::Added(value) => item("added", label, &code(value)),
+            Node::Deleted(value) => item("deleted", label, &code(value)),
+            Node::Modified(old_value, new_value) => {
+                let content = format!(
+                    "<del>{}</del> <ins>{}</ins>",
+                    code(old_value),
+                    code(new_value)
+                );
+                item("modified", label, &content)
+            }
+            Node::TextDiff(chunks) => item("textdiff", label, &text_diff(chunks)),
+            Node::Container { kind, entries } => {
+                self.container(label, kind, entries, output);
+                return;
+            }
+            Node::None => return,
+        };
+        output.push(Work::Markup(item));
+    }
+
+    /// Pushes the markup of a container and of its entries, with the runs of unchanged entries
+    /// away from the changes collapsed
+    fn container<'d, 'a>(
+        &self,
+        label: Option<&str>,
+        kind: ContainerKind,
+        entries: Vec<Entry<'d, 'a>>,
+        output: &mut Vec<Work<'d, 'a>>,
+    ) {
+        let (class, open, close) = match kind {
+            ContainerKind::Object => ("object", "{", "}"),
+            ContainerKind::Array => ("array", "[", "]"),
+            ContainerKind::Unordered => ("unordered", "[", "]"),
+            ContainerKind::Keyed => ("keyed", "[", "]"),
+        };
+        output.push(Work::Markup(format!(
+            "<li class=\"jsondiffpatch-node jsondiffpatch-{}\">{}<span class=\"jsondiffpatch-bracket\">{}</span>\n<ul>",
+            class,
+            property_name(label),
+            open
+        )));
+
+        let distances = tree::change_distances(&entries);
+        let mut collapsed = Vec::new();
+        for (entry, distance) in entries.into_iter().zip(distances) {
+            let markup = match entry {
+                Entry::Unchanged { label, value } => {
+                    let markup = item("unchanged", label.as_deref(), &code(value));
+                    if self
+                        .context_lines
+                        .is_some_and(|context_lines| distance > context_lines)
+                    {
+                        collapsed.push(markup);
+                        continue;
+                    }
+                    Work::Markup(markup)
+                }
+                Entry::Added { label, value } => {
+                    Work::Markup(item("added", label.as_deref(), &code(value)))
+                }
+                Entry::Deleted { label, value } => {
+                    Work::Markup(item("deleted", label.as_deref(), &code(value)))
+                }
+                Entry::Moved {
+                    label,
+                    value,
+                    new_index,
+                } => {
+                    let content = format!(
+                        "{}<span class=\"jsondiffpatch-moved-destination\">&rarr; {}</span>",
+                        code(value),
+                        new_index
+                    );
+                    Work::Markup(item("moved", Some(&label), &content))
+                }
+                Entry::Changed { label, left, delta } => Work::Node { label, left, delta },
+            };
+            if !collapsed.is_empty() {
+                output.push(Work::Markup(collapse(std::mem::take(&mut collapsed))));
+            }
+            output.push(markup);
+        }
+        if !collapsed.is_empty() {
+            output.push(Work::Markup(collapse(collapsed)));
+        }
+
+        output.push(Work::Markup(format!(
+            "</ul>\n<span class=\"jsondiffpatch-bracket\">{}</span></li>",
+            close
+        )));
+    }
+}
+
+/// A property or an item, with its class of change
+fn item(class: &str, label: Option<&str>, content: &str) -> String {
+    format!(
+        "<li class=\"jsondiffpatch-{}\">{}{}</li>",
+        class,
+        property_name(label),
+        content
+    )
+}
+
+fn property_name(label: Option<&str>) -> String {
+    label
+        .map(|label| {
+            format!(
+                "<span class=\"jsondiffpatch-property-name\">{}</span>",
+                escape(label)
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// A run of unchanged properties or items, collapsed
+fn collapse(items: Vec<String>) -> String {
+    format!(
+        "<li class=\"jsondiffpatch-collapsed\"><details><summary>{} unchanged</summary>\n<ul>\n{}\n</ul>\n</details></li>",
+        items.len(),
+        items.join("\n")
+    )
+}
+
+fn code(value: &Value) -> String {
+    format!("<code>{}</code>", escape(&value.to_string()))
+}
+
+/// The new text, with the removed parts of the old text inline
+fn text_diff(chunks: Vec<(Ops, String)>) -> String {
+    let mut content = String::from("<code>\"");
+    for (op, text) in chunks {
+        // escaped as in a JSON string
+        let text = Value::String(text).to_string();
+        let text = escape(&text[1..text.len() - 1]);
+        match op {
+            Ops::Equal => content.push_str(&text),
+            Ops::Delete => content.push_str(&format!("<del>{}</del>", text)),
+            Ops::Insert => content.push_str(&format!("<ins>{}</ins>", text)),
+        }
+    }
+    content.push_str("\"</code>");
+    content
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diff;
+    use serde_json::json;
+
+    #[test]
+    fn test_format_object() {
+        let left = json!({"a": 1, "b": "<b>", "c": true});
+        let right = json!({"a": 2, "c": true, "d": [1]});
+        let delta = diff(&left, &right).unwrap();
+
+        assert_eq!(
+            HtmlFormatter {
+                context_lines: None
+            }
+            .format(&left, &delta)
+            .unwrap(),
+            [
+                "<div class=\"jsondiffpatch-delta\">",
+                "<ul>",
+                "<li class=\"jsondiffpatch-node jsondiffpatch-object\"><span class=\"jsondiffpatch-bracket\">{</span>",
+                "<ul>",
+                "<li class=\"jsondiffpatch-modified\"><span class=\"jsondiffpatch-property-name\">a</span><del><code>1</code></del> <ins><code>2</code></ins></li>",
+                "<li class=\"jsondiffpatch-deleted\"><span class=\"jsondiffpatch-property-name\">b</span><code>&quot;&lt;b&gt;&quot;</code></li>",
+                "<li class=\"jsondiffpatch-unchanged\"><span class=\"jsondiffpatch-property-name\">c</span><code>true</code></li>",
+                "<li class=\"jsondiffpatch-added\"><span class=\"jsondiffpatch-property-name\">d</span><code>[1]</code></li>",
+                "</ul>",
+                "<span class=\"jsondiffpatch-bracket\">}</span></li>",
+                "</ul>",
+                "</div>",
+                "",
+            ]
+            .join("\n")
+        );
+
+        let page = format_page(&left, &delta).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains(STYLE));
+        assert!(page.contains(&format(&left, &delta).unwrap()));
+    }
+
+    #[test]
+    fn test_format_array() {
+        let left = json!([1, 2, 3, 4, 5, 6, 7, 8]);
+        let right = json!([2, 3, 4, 5, 6, 7, 1, 9]);
+        let delta = diff(&left, &right).unwrap();
+        let html = HtmlFormatter {
+            context_lines: Some(1),
+        }
+        .format(&left, &delta)
+        .unwrap();
+
+        assert!(html.contains(
+            "<li class=\"jsondiffpatch-moved\"><span class=\"jsondiffpatch-property-name\">0</span><code>1</code><span class=\"jsondiffpatch-moved-destination\">&rarr; 6</span></li>"
+        ));
+        assert!(html.contains(
+            "<li class=\"jsondiffpatch-deleted\"><span class=\"jsondiffpatch-property-name\">7</span><code>8</code></li>"
+        ));
+        assert!(html.contains(
+            "<li class=\"jsondiffpatch-added\"><span class=\"jsondiffpatch-property-name\">7</span><code>9</code></li>"
+        ));
+        // the items 3 to 6, at the new indices 1 to 4
+        assert!(html.contains("<details><summary>4 unchanged</summary>"));
+        assert_eq!(html.matches("jsondiffpatch-unchanged").count(), 6);
+    }
+
+    #[test]
+    fn test_format_text_diff() {
+        let left = json!("The quick brown fox jumps over the lazy dog, again and again and again.");
+        let right =
+            json!("The quick red fox jumps over the <lazy> dog, again and again and again.");
+        let delta = diff(&left, &right).unwrap();
+        assert!(matches!(delta, Delta::TextDiff(_)));
+
+        assert!(format(&left, &delta).unwrap().contains(
+            "<li class=\"jsondiffpatch-textdiff\"><code>\"The quick <del>b</del>r<del>own</del><ins>ed</ins> fox jumps over the <ins>&lt;</ins>lazy<ins>&gt;</ins> dog, again and again and again.\"</code></li>"
+        ));
+    }
+}
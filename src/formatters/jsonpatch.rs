@@ -0,0 +1,1118 @@
+//! Converts deltas to [RFC 6902](https://www.rfc-editor.org/rfc/rfc6902) JSON Patch operations,
+//! and applies or imports JSON Patch operations.
+
+use crate::diffpatcher::DiffPatcher;
+use crate::errors::{JsonDiffPatchError, JsonPatchError};
+use crate::path::{parse_pointer, pointer_child};
+use crate::pipeline::arrays::{item_key, ArrayIndexMap};
+use crate::types::{ArrayDeltaIndex, Delta, KeyedOrder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Number, Value};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
+/// A JSON Patch operation, where paths are JSON pointers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JsonPatchFormatter {
+    /// Precedes every operation removing, replacing or moving a value with a `test` of that value,
+    /// so that the patch is rejected by documents that changed in the meantime
+    pub emit_tests: bool,
+}
+
+/// Formats a delta as JSON Patch operations, see [`JsonPatchFormatter::format`]
+pub fn format(left: &Value, delta: &Delta) -> Result<Vec<Operation>, JsonDiffPatchError> {
+    JsonPatchFormatter::default().format(left, delta)
+}
+
+/// Applies JSON Patch operations in sequence, each of them turned into a delta applied by the
+/// patch pipeline
+pub fn apply(left: &Value, operations: &[Operation]) -> Result<Value, JsonDiffPatchError> {
+    let mut document = Cow::Borrowed(left);
+    for (index, operation) in operations.iter().enumerate() {
+        if let Some(patched) = apply_operation(&document, operation, index)? {
+            document = Cow::Owned(patched);
+        }
+    }
+    Ok(document.into_owned())
+}
+
+/// Converts JSON Patch operations into the delta from `left` to the document they lead to, as
+/// `diffpatcher` diffs them, or `None` when they change nothing
+pub fn to_delta(
+    diffpatcher: &DiffPatcher,
+    left: &Value,
+    operations: &[Operation],
+) -> Result<Option<Delta<'static>>, JsonDiffPatchError> {
+    let patched = apply(left, operations)?;
+    Ok(diffpatcher.try_diff(left, &patched)?.map(Delta::into_owned))
+}
+
+/// Applies one operation, returning `None` when it leaves the document unchanged
+fn apply_operation(
+    document: &Value,
+    operation: &Operation,
+    index: usize,
+) -> Result<Option<Value>, JsonDiffPatchError> {
+    let delta = match operation {
+        Operation::Add { path, value } => add_delta(document, path, value.clone(), index)?,
+        Operation::Remove { path } => remove_delta(document, path, index)?,
+        Operation::Replace { path, value } => replace_delta(document, path, value.clone(), index)?,
+        Operation::Move { from, path } => {
+            let value = Location::new(document, from, index)?.value()?.clone();
+            if path == from {
+                return Ok(None);
+            }
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(JsonPatchError::MoveIntoChild {
+                    index,
+                    from: from.clone(),
+                    path: path.clone(),
+                }
+                .into());
+            }
+            let removed = patch(document, remove_delta(document, from, index)?, index)?;
+            let delta = add_delta(&removed, path, value, index)?;
+            return patch(&removed, delta, index).map(Some);
+        }
+        Operation::Copy { from, path } => {
+            let value = Location::new(document, from, index)?.value()?.clone();
+            add_delta(document, path, value, index)?
+        }
+        Operation::Test { path, value } => {
+            let found = Location::new(document, path, index)?.value()?;
+            if !json_equal(found, value) {
+                return Err(JsonPatchError::TestFailed {
+                    index,
+                    path: path.clone(),
+                    expected: value.clone(),
+                    found: found.clone(),
+                }
+                .into());
+            }
+            return Ok(None);
+        }
+    };
+    patch(document, delta, index).map(Some)
+}
+
+fn patch(document: &Value, delta: Delta, index: usize) -> Result<Value, JsonDiffPatchError> {
+    crate::try_patch(document, delta)?.ok_or_else(|| JsonPatchError::RemoveRoot { index }.into())
+}
+
+/// The delta adding `value` at `pointer`, into an object or an array
+fn add_delta(
+    document: &Value,
+    pointer: &str,
+    value: Value,
+    index: usize,
+) -> Result<Delta<'static>, JsonDiffPatchError> {
+    let location = Location::new(document, pointer, index)?;
+    let leaf = match &location.child {
+        None => Delta::Added(Cow::Owned(value)),
+        Some((Value::Object(_), _)) => Delta::Added(Cow::Owned(value)),
+        Some((Value::Array(array), name)) => {
+            let new_index = match name.as_str() {
+                "-" => array.len(),
+                name => array_index(name)
+                    .filter(|&new_index| new_index <= array.len())
+                    .ok_or_else(|| location.not_found())?,
+            };
+            Delta::Array(vec![(
+                ArrayDeltaIndex::NewOrModified(new_index),
+                Delta::Added(Cow::Owned(value)),
+            )])
+        }
+        Some(_) => return Err(location.not_found().into()),
+    };
+    Ok(location.wrap(leaf))
+}
+
+/// The delta removing the value at `pointer`
+fn remove_delta(
+    document: &Value,
+    pointer: &str,
+    index: usize,
+) -> Result<Delta<'static>, JsonDiffPatchError> {
+    let location = Location::new(document, pointer, index)?;
+    location.value()?;
+    // the removed value is not needed to patch, so it is not copied
+    let leaf = match &location.child {
+        None => return Err(JsonPatchError::RemoveRoot { index }.into()),
+        Some((Value::Array(_), name)) => Delta::Array(vec![(
+            ArrayDeltaIndex::RemovedOrMoved(array_index(name).expect("value exists")),
+            Delta::Deleted(None),
+        )]),
+        Some(_) => Delta::Deleted(None),
+    };
+    Ok(location.wrap(leaf))
+}
+
+/// The delta replacing the value at `pointer` with `value`
+fn replace_delta(
+    document: &Value,
+    pointer: &str,
+    value: Value,
+    index: usize,
+) -> Result<Delta<'static>, JsonDiffPatchError> {
+    let location = Location::new(document, pointer, index)?;
+    location.value()?;
+    let modified = Delta::Modified(None, Cow::Owned(value));
+    let leaf = match &location.child {
+        Some((Value::Array(_), name)) => Delta::Array(vec![(
+            ArrayDeltaIndex::NewOrModified(array_index(name).expect("value exists")),
+            modified,
+        )]),
+        _ => modified,
+    };
+    Ok(location.wrap(leaf))
+}
+
+/// Where a JSON pointer leads in a document: the containers on the way with the child taken in
+/// each of them, and the last container with the name of the pointed child (`None` at the root)
+struct Location<'v> {
+    pointer: String,
+    index: usize,
+    parents: Vec<(&'v Value, String)>,
+    child: Option<(&'v Value, String)>,
+    document: &'v Value,
+}
+
+impl<'v> Location<'v> {
+    fn new(document: &'v Value, pointer: &str, index: usize) -> Result<Self, JsonPatchError> {
+        let mut segments = parse_pointer(pointer)
+            .ok_or_else(|| JsonPatchError::InvalidPointer {
+                index,
+                pointer: pointer.to_string(),
+            })?
+            .into_iter();
+        let mut location = Self {
+            pointer: pointer.to_string(),
+            index,
+            parents: Vec::new(),
+            child: None,
+            document,
+        };
+
+        let mut container = document;
+        let Some(mut name) = segments.next() else {
+            return Ok(location);
+        };
+        for next_name in segments {
+            let next_container = child(container, &name).ok_or_else(|| location.not_found())?;
+            location.parents.push((container, name));
+            (container, name) = (next_container, next_name);
+        }
+        location.child = Some((container, name));
+        Ok(location)
+    }
+
+    /// The value the pointer leads to, which must exist
+    fn value(&self) -> Result<&'v Value, JsonPatchError> {
+        match &self.child {
+            None => Ok(self.document),
+            Some((container, name)) => child(container, name).ok_or_else(|| self.not_found()),
+        }
+    }
+
+    /// Nests a delta of the last container in the deltas of its parents, up to the root
+    fn wrap(&self, delta: Delta<'static>) -> Delta<'static> {
+        let wrap_in = |delta, (container, name): &(&Value, String)| match container {
+            Value::Array(_) => Delta::Array(vec![(
+                ArrayDeltaIndex::NewOrModified(array_index(name).expect("parents exist")),
+                delta,
+            )]),
+            _ => Delta::Object(HashMap::from([(name.clone(), delta)])),
+        };
+        let delta = match &self.child {
+            Some(child @ (Value::Object(_), _)) => wrap_in(delta, child),
+            _ => delta,
+        };
+        self.parents.iter().rev().fold(delta, wrap_in)
+    }
+
+    fn not_found(&self) -> JsonPatchError {
+        JsonPatchError::PathNotFound {
+            index: self.index,
+            pointer: self.pointer.clone(),
+        }
+    }
+}
+
+/// Whether two values are equal as JSON Patch tests them, where numbers are compared by their
+/// numeric value (e.g. `1` equals `1.0`)
+fn json_equal(left: &Value, right: &Value) -> bool {
+    // compared with an explicit stack, as deeply nested values would overflow a recursive `==`
+    let mut pending = vec![(left, right)];
+    while let Some((left, right)) = pending.pop() {
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => {
+                let integer = |number: &Number| {
+                    number
+                        .as_i64()
+                        .map(i128::from)
+                        .or_else(|| number.as_u64().map(i128::from))
+                };
+                let equal = match (integer(left), integer(right)) {
+                    (Some(left), Some(right)) => left == right,
+                    _ => left.as_f64() == right.as_f64(),
+                };
+                if !equal {
+                    return false;
+                }
+            }
+            (Value::Array(left), Value::Array(right)) => {
+                if left.len() != right.len() {
+                    return false;
+                }
+                pending.extend(left.iter().zip(right));
+            }
+            (Value::Object(left), Value::Object(right)) => {
+                if left.len() != right.len() {
+                    return false;
+                }
+                for (key, left_value) in left {
+                    match right.get(key) {
+                        Some(right_value) => pending.push((left_value, right_value)),
+                        None => return false,
+                    }
+                }
+            }
+            (left, right) => {
+                if left != right {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+fn child<'v>(container: &'v Value, name: &str) -> Option<&'v Value> {
+    match container {
+        Value::Object(object) => object.get(name),
+        Value::Array(array) => array.get(array_index(name)?),
+        _ => None,
+    }
+}
+
+/// The index named by a JSON pointer segment, which has no sign and no leading zero
+fn array_index(name: &str) -> Option<usize> {
+    if name.is_empty()
+        || !name.bytes().all(|byte| byte.is_ascii_digit())
+        || (name.len() > 1 && name.starts_with('0'))
+    {
+        return None;
+    }
+    name.parse().ok()
+}
+
+/// A delta still to be formatted, along with the value it applies to
+struct Pending<'d, 'a> {
+    path: String,
+    left: Option<&'d Value>,
+    delta: &'d Delta<'a>,
+}
+
+/// The item to insert at a new index of an array
+enum Insertion<'d> {
+    Added(&'d Value),
+    Moved(usize),
+}
+
+impl JsonPatchFormatter {
+    /// Returns the operations that turn `left` into the document that the delta leads to, to be
+    /// applied in sequence.
+    ///
+    /// The delta is checked against `left`, which also provides the values of `test` operations
+    /// and the positions of the items of unordered and keyed arrays.
+    pub fn format(
+        &self,
+        left: &Value,
+        delta: &Delta,
+    ) -> Result<Vec<Operation>, JsonDiffPatchError> {
+        let mut operations = Vec::new();
+        // nested deltas are formatted once the structural changes of their parent are done, as
+        // their paths are positions in the changed parent
+        let mut pending = vec![Pending {
+            path: String::new(),
+            left: Some(left),
+            delta,
+        }];
+        while let Some(Pending { path, left, delta }) = pending.pop() {
+            let mut nested = Vec::new();
+            self.format_delta(&path, left, delta, &mut operations, &mut nested)?;
+            pending.extend(nested.into_iter().rev());
+        }
+        Ok(operations)
+    }
+
+    fn format_delta<'d, 'a>(
+        &self,
+        path: &str,
+        left: Option<&'d Value>,
+        delta: &'d Delta<'a>,
+        operations: &mut Vec<Operation>,
+        nested: &mut Vec<Pending<'d, 'a>>,
+    ) -> Result<(), JsonDiffPatchError> {
+        let invalid_target = |patch: &str| JsonDiffPatchError::InvalidPatchToTarget {
+            patch: patch.to_string(),
+        };
+
+        match delta {
+            Delta::Added(value) => operations.push(Operation::Add {
+                path: path.to_string(),
+                value: value.clone().into_owned(),
+            }),
+            Delta::Modified(_, new_value) => match left {
+                Some(left) => {
+                    self.test(path, left, operations);
+                    operations.push(Operation::Replace {
+                        path: path.to_string(),
+                        value: new_value.clone().into_owned(),
+                    });
+                }
+                // a missing property is set
+                None => operations.push(Operation::Add {
+                    path: path.to_string(),
+                    value: new_value.clone().into_owned(),
+                }),
+            },
+            Delta::Deleted(_) => {
+                // deleting a missing property changes nothing
+                if let Some(left) = left {
+                    self.test(path, left, operations);
+                    operations.push(Operation::Remove {
+                        path: path.to_string(),
+                    });
+                }
+            }
+            Delta::TextDiff(_) => {
+                let left = left.ok_or_else(|| invalid_target("text diff"))?;
+                let new_text = crate::try_patch(left, delta.clone())?
+                    .ok_or_else(|| invalid_target("text diff"))?;
+                self.test(path, left, operations);
+                operations.push(Operation::Replace {
+                    path: path.to_string(),
+                    value: new_text,
+                });
+            }
+            Delta::Moved { .. } => {
+                return Err(JsonDiffPatchError::InvalidPatch(
+                    "a move can only be applied to an item of an array".to_string(),
+                ));
+            }
+            Delta::Object(changes) => {
+                let object = left
+                    .and_then(Value::as_object)
+                    .ok_or_else(|| invalid_target("object"))?;
+                let mut keys: Vec<&String> = changes.keys().collect();
+                keys.sort_unstable();
+                for key in keys {
+                    nested.push(Pending {
+                        path: pointer_child(path, key),
+                        left: object.get(key),
+                        delta: &changes[key],
+                    });
+                }
+            }
+            Delta::Array(changes) => {
+                let array = left
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid_target("array"))?;
+                self.format_array(path, array, changes, operations, nested)?;
+            }
+            Delta::Unordered { removed, added } => {
+                let array = left
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid_target("unordered array"))?;
+                let mut current: Vec<&Value> = array.iter().collect();
+                for item in removed {
+                    let position = current
+                        .iter()
+                        .position(|value| *value == item.as_ref())
+                        .ok_or_else(|| JsonDiffPatchError::UnorderedItemNotFound {
+                            item: item.clone().into_owned(),
+                        })?;
+                    let item_path = pointer_child(path, &position.to_string());
+                    self.test(&item_path, current[position], operations);
+                    operations.push(Operation::Remove { path: item_path });
+                    current.remove(position);
+                }
+                for (offset, item) in added.iter().enumerate() {
+                    operations.push(Operation::Add {
+                        path: pointer_child(path, &(current.len() + offset).to_string()),
+                        value: item.clone().into_owned(),
+                    });
+                }
+            }
+            Delta::Keyed {
+                key,
+                changes,
+                order,
+            } => {
+                let array = left
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| invalid_target("keyed array"))?;
+                self.format_keyed_array(
+                    path,
+                    array,
+                    key,
+                    changes,
+                    order.as_ref(),
+                    operations,
+                    nested,
+                )?;
+            }
+            Delta::None => {}
+        }
+        Ok(())
+    }
+
+    /// Formats an array delta: deletions first, from the last one, then additions and moves in
+    /// the order of their new index, and finally the nested changes at their new index
+    fn format_array<'d, 'a>(
+        &self,
+        path: &str,
+        array: &'d [Value],
+        changes: &'d [(ArrayDeltaIndex, Delta<'a>)],
+        operations: &mut Vec<Operation>,
+        nested: &mut Vec<Pending<'d, 'a>>,
+    ) -> Result<(), JsonDiffPatchError> {
+        let mut deleted = Vec::new();
+        let mut insertions = Vec::new();
+        let mut nested_changes = Vec::new();
+        for (index, delta) in changes {
+            match (index, delta) {
+                (ArrayDeltaIndex::RemovedOrMoved(old_index), Delta::Deleted(_)) => {
+                    deleted.push(*old_index)
+                }
+                (ArrayDeltaIndex::RemovedOrMoved(old_index), Delta::Moved { new_index, .. }) => {
+                    insertions.push((*new_index, Insertion::Moved(*old_index)))
+                }
+                (ArrayDeltaIndex::RemovedOrMoved(_), _) => {
+                    return Err(JsonDiffPatchError::InvalidPatch(
+                        "only removal or move can be applied at original array indices".to_string(),
+                    ));
+                }
+                (ArrayDeltaIndex::NewOrModified(new_index), Delta::Added(value)) => {
+                    insertions.push((*new_index, Insertion::Added(value)))
+                }
+                (ArrayDeltaIndex::NewOrModified(_), Delta::None) => {}
+                (ArrayDeltaIndex::NewOrModified(new_index), delta) => {
+                    nested_changes.push((*new_index, delta))
+                }
+            }
+        }
+
+        // the items of the array as the operations are applied, by their original index (`None`
+        // for added items)
+        let mut current: Vec<Option<usize>> = (0..array.len()).map(Some).collect();
+        let item_path = |index: usize| pointer_child(path, &index.to_string());
+
+        // from the last one, so that the original index is also the current one
+        deleted.sort_unstable_by(|a, b| b.cmp(a));
+        for old_index in deleted {
+            let value = array
+                .get(old_index)
+                .ok_or(JsonDiffPatchError::IndexOutOfBoundsRemove {
+                    index: old_index,
+                    length: array.len(),
+                })?;
+            self.test(&item_path(old_index), value, operations);
+            operations.push(Operation::Remove {
+                path: item_path(old_index),
+            });
+            current.remove(old_index);
+        }
+
+        // the delta inserts items at their new index once all moved items are taken out, so the
+        // moved items that are still in place are skipped when locating the new indices
+        let mut moving: HashSet<usize> = insertions
+            .iter()
+            .filter_map(|(_, insertion)| match insertion {
+                Insertion::Moved(old_index) => Some(*old_index),
+                Insertion::Added(_) => None,
+            })
+            .collect();
+        insertions.sort_by_key(|(new_index, _)| *new_index);
+        for (new_index, insertion) in insertions {
+            match insertion {
+                Insertion::Added(value) => {
+                    let position = insert_position(&current, &moving, new_index)?;
+                    current.insert(position, None);
+                    operations.push(Operation::Add {
+                        path: item_path(position),
+                        value: value.clone(),
+                    });
+                }
+                Insertion::Moved(old_index) => {
+                    let from = current
+                        .iter()
+                        .position(|&item| item == Some(old_index))
+                        .ok_or(JsonDiffPatchError::IndexOutOfBoundsMove {
+                            from: old_index,
+                            to: new_index,
+                            length: array.len(),
+                        })?;
+                    current.remove(from);
+                    moving.remove(&old_index);
+                    let position = insert_position(&current, &moving, new_index)?;
+                    current.insert(position, Some(old_index));
+                    if from != position {
+                        self.test(&item_path(from), &array[old_index], operations);
+                        operations.push(Operation::Move {
+                            from: item_path(from),
+                            path: item_path(position),
+                        });
+                    }
+                }
+            }
+        }
+
+        let index_map = ArrayIndexMap::new(changes);
+        nested_changes.sort_by_key(|(new_index, _)| *new_index);
+        for (new_index, delta) in nested_changes {
+            let left = index_map
+                .old_index(new_index)
+                .and_then(|old_index| array.get(old_index))
+                .ok_or(JsonDiffPatchError::IndexOutOfBoundsModify {
+                    index: new_index,
+                    length: array.len(),
+                })?;
+            nested.push(Pending {
+                path: item_path(new_index),
+                left: Some(left),
+                delta,
+            });
+        }
+        Ok(())
+    }
+
+    /// Formats a keyed array delta, locating its items by key in the array: deletions first, from
+    /// the last one, then additions at the end, moves into the recorded order if any, and finally
+    /// the nested changes at their new index
+    #[allow(clippy::too_many_arguments)]
+    fn format_keyed_array<'d, 'a>(
+        &self,
+        path: &str,
+        array: &'d [Value],
+        key_field: &str,
+        changes: &'d [(String, Delta<'a>)],
+        order: Option<&KeyedOrder>,
+        operations: &mut Vec<Operation>,
+        nested: &mut Vec<Pending<'d, 'a>>,
+    ) -> Result<(), JsonDiffPatchError> {
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for (index, item) in array.iter().enumerate() {
+            if let Some(key) = item_key(item, key_field) {
+                positions.entry(key).or_insert(index);
+            }
+        }
+        let position = |key: &str| {
+            positions
+                .get(key)
+                .copied()
+                .ok_or_else(|| JsonDiffPatchError::KeyedItemNotFound {
+                    key_field: key_field.to_string(),
+                    key: key.to_string(),
+                })
+        };
+        let item_path = |index: usize| pointer_child(path, &index.to_string());
+
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        let mut nested_changes = Vec::new();
+        for (key, delta) in changes {
+            match delta {
+                Delta::Added(value) => {
+                    if positions.contains_key(key) {
+                        return Err(JsonDiffPatchError::KeyedItemExists {
+                            key_field: key_field.to_string(),
+                            key: key.clone(),
+                        });
+                    }
+                    added.push((key, value));
+                }
+                Delta::Deleted(_) => deleted.push(position(key)?),
+                Delta::Moved { .. } => {
+                    return Err(JsonDiffPatchError::InvalidPatch(
+                        "items of keyed arrays cannot be moved".to_string(),
+                    ));
+                }
+                Delta::None => {}
+                delta => nested_changes.push((key, position(key)?, delta)),
+            }
+        }
+
+        deleted.sort_unstable_by(|a, b| b.cmp(a));
+        for &index in &deleted {
+            self.test(&item_path(index), &array[index], operations);
+            operations.push(Operation::Remove {
+                path: item_path(index),
+            });
+        }
+        let length = array.len() - deleted.len();
+        for (offset, (_, value)) in added.iter().enumerate() {
+            operations.push(Operation::Add {
+                path: item_path(length + offset),
+                value: value.as_ref().clone(),
+            });
+        }
+
+        // the keys of the items as they are moved into order, one item at a time
+        let mut current: Vec<Option<String>> = Vec::new();
+        if let Some(order) = order {
+            current = (0..array.len())
+                .filter(|index| !deleted.contains(index))
+                .map(|index| item_key(&array[index], key_field))
+                .chain(added.iter().map(|(key, _)| Some(key.to_string())))
+                .collect();
+            let mismatch = || {
+                JsonDiffPatchError::InvalidPatch(format!(
+                    "the order of the keyed array does not match its items by {}",
+                    key_field
+                ))
+            };
+            if current.len() != order.right.len() {
+                return Err(mismatch());
+            }
+            for (index, key) in order.right.iter().enumerate() {
+                let from = current[index..]
+                    .iter()
+                    .position(|item| item.as_ref() == Some(key))
+                    .ok_or_else(mismatch)?
+                    + index;
+                if from != index {
+                    let item = current.remove(from);
+                    current.insert(index, item);
+                    operations.push(Operation::Move {
+                        from: item_path(from),
+                        path: item_path(index),
+                    });
+                }
+            }
+        }
+
+        for (key, index, delta) in nested_changes {
+            if deleted.contains(&index) {
+                return Err(JsonDiffPatchError::KeyedItemNotFound {
+                    key_field: key_field.to_string(),
+                    key: key.clone(),
+                });
+            }
+            let new_index = match order {
+                Some(_) => current
+                    .iter()
+                    .position(|item| item.as_ref() == Some(key))
+                    .expect("the ordered items contain the kept ones"),
+                None => index - deleted.iter().filter(|&&removed| removed < index).count(),
+            };
+            nested.push(Pending {
+                path: item_path(new_index),
+                left: Some(&array[index]),
+                delta,
+            });
+        }
+        Ok(())
+    }
+
+    fn test(&self, path: &str, value: &Value, operations: &mut Vec<Operation>) {
+        if self.emit_tests {
+            operations.push(Operation::Test {
+                path: path.to_string(),
+                value: value.clone(),
+            });
+        }
+    }
+}
+
+/// The position in `current` where an item lands when inserted at `new_index` of the array
+/// without its `moving` items
+fn insert_position(
+    current: &[Option<usize>],
+    moving: &HashSet<usize>,
+    new_index: usize,
+) -> Result<usize, JsonDiffPatchError> {
+    let mut settled = 0;
+    for (position, item) in current.iter().enumerate() {
+        if item.is_some_and(|old_index| moving.contains(&old_index)) {
+            continue;
+        }
+        if settled == new_index {
+            return Ok(position);
+        }
+        settled += 1;
+    }
+    if settled == new_index {
+        Ok(current.len())
+    } else {
+        Err(JsonDiffPatchError::IndexOutOfBoundsInsert {
+            index: new_index,
+            length: settled,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ArrayOptions, Options};
+    use serde_json::json;
+
+    #[test]
+    fn test_format_object_changes() {
+        let left = json!({"a": 1, "b": {"c": "x", "d/e": true}, "f": [1], "g": "text"});
+        let right = json!({"a": 2, "b": {"c": "y"}, "f": [1], "h": null});
+        let delta = crate::diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(format(&left, &delta).unwrap()).unwrap(),
+            json!([
+                {"op": "replace", "path": "/a", "value": 2},
+                {"op": "replace", "path": "/b/c", "value": "y"},
+                {"op": "remove", "path": "/b/d~1e"},
+                {"op": "remove", "path": "/g"},
+                {"op": "add", "path": "/h", "value": null}
+            ])
+        );
+
+        let long_text = "The quick brown fox jumps over the lazy dog, again and again and again.";
+        let left = json!({"t": long_text});
+        let right = json!({"t": long_text.replace("lazy", "sleepy")});
+        let delta = crate::diff(&left, &right).unwrap();
+        assert!(
+            matches!(&delta, Delta::Object(changes) if matches!(changes["t"], Delta::TextDiff(_)))
+        );
+        assert_eq!(
+            apply(&left, &format(&left, &delta).unwrap()).unwrap(),
+            right
+        );
+
+        let (one, x) = (json!(1), json!("x"));
+        let delta = crate::diff(&one, &x).unwrap();
+        assert_eq!(
+            format(&one, &delta).unwrap(),
+            vec![Operation::Replace {
+                path: String::new(),
+                value: json!("x")
+            }]
+        );
+    }
+
+    #[test]
+    fn test_format_array_changes() {
+        let cases = [
+            (json!([1, 2, 3]), json!([3, 1, 2])),
+            (json!([1, 2, 3, 4, 5, 6, 7]), json!([1, 2, 4, 5, 3, 6, 7])),
+            (
+                json!(["a", "b", "c", "d", "e", "f"]),
+                json!(["f", "c", "a", "e", "b", "d"]),
+            ),
+            (
+                json!(["a", "b", "c", "d", "e"]),
+                json!(["x", "b", "d", "y", "e", "z"]),
+            ),
+            (json!([1, 2, 3, 4]), json!([4, "x", 3, 2, 1, "y"])),
+            (json!([]), json!([1, 2])),
+            (json!([1, 2]), json!([])),
+            (
+                json!({"list": [{"id": 1, "v": 1}, {"id": 2, "v": 2}, {"id": 3, "v": 3}]}),
+                json!({"list": [{"id": 3, "v": 4}, {"id": 1, "v": 1}, {"id": 4}, {"id": 2, "v": 5}]}),
+            ),
+            (json!([[1, 2], [3, 4], 5]), json!([5, [3, 4, 6], [1]])),
+        ];
+        let diffpatchers = [
+            DiffPatcher::new(Some(Options {
+                object_hash: Some(std::sync::Arc::new(|item: &Value, _index: usize| {
+                    item.get("id").map(|id| id.to_string())
+                })),
+                ..Default::default()
+            })),
+            DiffPatcher::new(Some(Options {
+                arrays: Some(ArrayOptions {
+                    detect_move: Some(false),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })),
+        ];
+        let formatter = JsonPatchFormatter { emit_tests: true };
+
+        for diffpatcher in &diffpatchers {
+            for (left, right) in &cases {
+                let delta = diffpatcher.diff(left, right).unwrap();
+                let operations = formatter.format(left, &delta).unwrap();
+                assert_eq!(
+                    &apply(left, &operations).unwrap(),
+                    right,
+                    "{:?}",
+                    operations
+                );
+            }
+        }
+
+        // a small linear congruential generator, to get reproducible arrays
+        let mut seed: u64 = 7;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+        for _ in 0..300 {
+            let alphabet = next(8) + 1;
+            let left: Value = (0..next(12)).map(|_| next(alphabet)).collect();
+            let right: Value = (0..next(12)).map(|_| next(alphabet)).collect();
+            if let Some(delta) = crate::diff(&left, &right) {
+                let operations = formatter.format(&left, &delta).unwrap();
+                assert_eq!(
+                    apply(&left, &operations).unwrap(),
+                    right,
+                    "{:?}",
+                    operations
+                );
+            }
+        }
+
+        let (left, right) = (json!([1, 2, 3]), json!([3, 1, 2, 4]));
+        let delta = crate::diff(&left, &right).unwrap();
+        assert_eq!(
+            format(&left, &delta).unwrap(),
+            vec![
+                Operation::Move {
+                    from: "/2".to_string(),
+                    path: "/0".to_string()
+                },
+                Operation::Add {
+                    path: "/3".to_string(),
+                    value: json!(4)
+                },
+            ]
+        );
+
+        let err = format(&json!([1]), &delta).unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::IndexOutOfBoundsMove { .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_format_unordered_and_keyed_arrays() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            arrays: Some(ArrayOptions {
+                unordered_paths: Some(vec!["/tags".to_string()]),
+                keyed_paths: Some([("/items".to_string(), "name".to_string())].into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let left = json!({
+            "tags": ["a", "b", "a", "c"],
+            "items": [{"name": "x", "v": 1}, {"name": "y", "v": 2}, {"name": "z", "v": 3}]
+        });
+        let right = json!({
+            "tags": ["c", "a", "d"],
+            "items": [{"name": "w"}, {"name": "z", "v": 4}, {"name": "x", "v": 1}]
+        });
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+        let operations = JsonPatchFormatter { emit_tests: true }
+            .format(&left, &delta)
+            .unwrap();
+        // the items of keyed arrays are put in order, unordered ones are appended
+        let applied = apply(&left, &operations).unwrap();
+        assert_eq!(applied["items"], right["items"]);
+        assert!(diffpatcher.diff(&applied, &right).is_none());
+        assert_eq!(
+            operations
+                .iter()
+                .filter(|operation| matches!(operation, Operation::Test { .. }))
+                .count(),
+            4
+        );
+    }
+
+    fn operations(operations: Value) -> Vec<Operation> {
+        serde_json::from_value(operations).unwrap()
+    }
+
+    #[test]
+    fn test_apply_operations() {
+        let cases = [
+            (
+                json!({"foo": "bar"}),
+                json!([{"op": "add", "path": "/baz", "value": "qux"}]),
+                json!({"baz": "qux", "foo": "bar"}),
+            ),
+            (
+                json!({"foo": ["bar", "baz"]}),
+                json!([{"op": "add", "path": "/foo/1", "value": "qux"}]),
+                json!({"foo": ["bar", "qux", "baz"]}),
+            ),
+            (
+                json!({"foo": ["bar"]}),
+                json!([{"op": "add", "path": "/foo/-", "value": ["abc", "def"]}]),
+                json!({"foo": ["bar", ["abc", "def"]]}),
+            ),
+            (
+                json!({"baz": "qux", "foo": ["bar", "qux", "baz"]}),
+                json!([
+                    {"op": "remove", "path": "/baz"},
+                    {"op": "remove", "path": "/foo/1"}
+                ]),
+                json!({"foo": ["bar", "baz"]}),
+            ),
+            (
+                json!({"baz": "qux", "foo": ["bar"]}),
+                json!([
+                    {"op": "replace", "path": "/baz", "value": "boo"},
+                    {"op": "replace", "path": "/foo/0", "value": {"a": 1}}
+                ]),
+                json!({"baz": "boo", "foo": [{"a": 1}]}),
+            ),
+            (
+                json!({"foo": {"bar": "baz", "waldo": "fred"}, "qux": {"corge": "grault"}}),
+                json!([{"op": "move", "from": "/foo/waldo", "path": "/qux/thud"}]),
+                json!({"foo": {"bar": "baz"}, "qux": {"corge": "grault", "thud": "fred"}}),
+            ),
+            (
+                json!({"foo": ["all", "grass", "cows", "eat"]}),
+                json!([{"op": "move", "from": "/foo/1", "path": "/foo/3"}]),
+                json!({"foo": ["all", "cows", "eat", "grass"]}),
+            ),
+            (
+                json!({"foo": {"a": [1]}, "/": 9, "~1": 10}),
+                json!([
+                    {"op": "test", "path": "/~01", "value": 10},
+                    {"op": "copy", "from": "/foo/a", "path": "/~1"},
+                    {"op": "add", "path": "/foo/a/0", "value": 0}
+                ]),
+                json!({"foo": {"a": [0, 1]}, "/": [1], "~1": 10}),
+            ),
+            (
+                json!({"foo": 1}),
+                json!([{"op": "replace", "path": "", "value": [1]}]),
+                json!([1]),
+            ),
+            (
+                // numbers are tested by their numeric value
+                json!({"a": [1, {"b": 2}]}),
+                json!([
+                    {"op": "test", "path": "/a", "value": [1.0, {"b": 2.0}]},
+                    {"op": "remove", "path": "/a/0"}
+                ]),
+                json!({"a": [{"b": 2}]}),
+            ),
+        ];
+
+        for (left, patch, expected) in cases {
+            let operations = operations(patch);
+            assert_eq!(
+                apply(&left, &operations).unwrap(),
+                expected,
+                "{:?}",
+                operations
+            );
+
+            let delta = to_delta(&DiffPatcher::new(None), &left, &operations)
+                .unwrap()
+                .unwrap();
+            let delta = Delta::from_value(serde_json::to_value(&delta).unwrap()).unwrap();
+            assert_eq!(crate::patch(&left, delta), Some(expected));
+        }
+
+        assert!(to_delta(
+            &DiffPatcher::new(None),
+            &json!({"a": [1]}),
+            &operations(json!([{"op": "test", "path": "/a/0", "value": 1}]))
+        )
+        .unwrap()
+        .is_none());
+
+        // the delta is diffed with the options of the given instance
+        let left = json!({"foo": ["all", "grass", "cows", "eat"]});
+        let operations = operations(json!([{"op": "move", "from": "/foo/1", "path": "/foo/3"}]));
+        let without_moves = DiffPatcher::new(Some(Options {
+            arrays: Some(ArrayOptions {
+                detect_move: Some(false),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        assert_eq!(
+            serde_json::to_value(to_delta(&DiffPatcher::new(None), &left, &operations).unwrap())
+                .unwrap(),
+            json!({"foo": {"_t": "a", "_1": ["", 3, 3]}})
+        );
+        assert_eq!(
+            serde_json::to_value(to_delta(&without_moves, &left, &operations).unwrap()).unwrap(),
+            json!({"foo": {"_t": "a", "_1": ["grass", null, 0], "3": ["grass"]}})
+        );
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let left = json!({"foo": "bar", "list": [1, 2], "nested": {"a": 1}});
+        let cases = [
+            json!([{"op": "add", "path": "/baz", "value": 1}, {"op": "test", "path": "/foo", "value": "baz"}]),
+            json!([{"op": "add", "path": "/baz/bat", "value": 1}]),
+            json!([{"op": "add", "path": "/list/3", "value": 1}]),
+            json!([{"op": "remove", "path": "/list/01"}]),
+            json!([{"op": "replace", "path": "/missing", "value": 1}]),
+            json!([{"op": "add", "path": "foo", "value": 1}]),
+            json!([{"op": "copy", "from": "/foo~2", "path": "/bar"}]),
+            json!([{"op": "move", "from": "/nested", "path": "/nested/a/b"}]),
+            json!([{"op": "remove", "path": ""}]),
+        ];
+        let errors: Vec<_> = cases
+            .into_iter()
+            .map(|patch| match apply(&left, &operations(patch)) {
+                Err(JsonDiffPatchError::JsonPatch(err)) => err,
+                result => panic!("expected a JSON Patch error, got {:?}", result),
+            })
+            .collect();
+
+        assert!(matches!(
+            &errors[0],
+            JsonPatchError::TestFailed { index: 1, path, expected, found }
+                if path == "/foo" && *expected == json!("baz") && *found == json!("bar")
+        ));
+        for err in &errors[1..5] {
+            assert!(
+                matches!(err, JsonPatchError::PathNotFound { index: 0, .. }),
+                "{:?}",
+                err
+            );
+        }
+        for err in &errors[5..7] {
+            assert!(
+                matches!(err, JsonPatchError::InvalidPointer { index: 0, .. }),
+                "{:?}",
+                err
+            );
+        }
+        assert!(matches!(
+            &errors[7],
+            JsonPatchError::MoveIntoChild { index: 0, .. }
+        ));
+        assert!(matches!(
+            &errors[8],
+            JsonPatchError::RemoveRoot { index: 0 }
+        ));
+        assert_eq!(
+            errors[1].to_string(),
+            "JSON Patch operation 0: there is no value at '/baz/bat'"
+        );
+    }
+}
@@ -0,0 +1,6 @@
+//! Formatters turning deltas into other representations of the changes.
+
+pub mod console;
+pub mod html;
+pub mod jsonpatch;
+mod tree;
@@ -0,0 +1,355 @@
+//! The changes of a delta as a tree of values, shared by the formatters showing the delta
+//! alongside the unchanged values of the document it applies to.
+
+use crate::errors::JsonDiffPatchError;
+use crate::pipeline::arrays::{item_key, ArrayIndexMap};
+use crate::pipeline::texts::DMP;
+use crate::types::{ArrayDeltaIndex, Delta, KeyedOrder};
+use diff_match_patch_rs::{Compat, Ops};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// What the delta of a value shows
+pub(crate) enum Node<'d, 'a> {
+    Added(&'d Value),
+    Deleted(&'d Value),
+    Modified(&'d Value, &'d Value),
+    /// the chunks of the new text, along with the removed chunks of the old text
+    TextDiff(Vec<(Ops, String)>),
+    Container {
+        kind: ContainerKind,
+        entries: Vec<Entry<'d, 'a>>,
+    },
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContainerKind {
+    Object,
+    Array,
+    Unordered,
+    Keyed,
+}
+
+/// A property or an item of a container, labeled by its key, by its index, or by its key field
+/// for keyed arrays
+pub(crate) enum Entry<'d, 'a> {
+    Unchanged {
+        label: Option<String>,
+        value: &'d Value,
+    },
+    Added {
+        label: Option<String>,
+        value: &'d Value,
+    },
+    Deleted {
+        label: Option<String>,
+        value: &'d Value,
+    },
+    /// an item moved away from the index of its label
+    Moved {
+        label: String,
+        value: &'d Value,
+        new_index: usize,
+    },
+    /// a value whose delta is shown as a node of its own
+    Changed {
+        label: Option<String>,
+        left: Option<&'d Value>,
+        delta: &'d Delta<'a>,
+    },
+}
+
+impl Entry<'_, '_> {
+    pub fn is_changed(&self) -> bool {
+        !matches!(self, Entry::Unchanged { .. })
+    }
+}
+
+/// The node showing the delta as it applies to `left`, the value it changes if any
+pub(crate) fn node<'d, 'a>(
+    left: Option<&'d Value>,
+    delta: &'d Delta<'a>,
+) -> Result<Node<'d, 'a>, JsonDiffPatchError> {
+    let invalid_target = |patch: &str| JsonDiffPatchError::InvalidPatchToTarget {
+        patch: patch.to_string(),
+    };
+
+    let node = match delta {
+        Delta::Added(value) => Node::Added(value.as_ref()),
+        Delta::Deleted(value) => Node::Deleted(left.or(value.as_deref()).unwrap_or(&Value::Null)),
+        Delta::Modified(old_value, new_value) => Node::Modified(
+            left.or(old_value.as_deref()).unwrap_or(&Value::Null),
+            new_value.as_ref(),
+        ),
+        Delta::TextDiff(_) => {
+            let left = left.ok_or_else(|| invalid_target("text diff"))?;
+            let (Value::String(old_text), Some(Value::String(new_text))) =
+                (left, crate::try_patch(left, delta.clone())?)
+            else {
+                return Err(invalid_target("text diff"));
+            };
+            let chunks = DMP
+                .diff_main::<Compat>(old_text, &new_text)?
+                .iter()
+                .map(|diff| (diff.op(), diff.data().iter().collect()))
+                .collect();
+            Node::TextDiff(chunks)
+        }
+        Delta::Moved { .. } => {
+            return Err(JsonDiffPatchError::InvalidPatch(
+                "a move can only be applied to an item of an array".to_string(),
+            ));
+        }
+        Delta::Object(changes) => {
+            let object = left
+                .and_then(Value::as_object)
+                .ok_or_else(|| invalid_target("object"))?;
+            let keys: BTreeSet<&String> = object.keys().chain(changes.keys()).collect();
+            let entries = keys
+                .into_iter()
+                .map(|key| match changes.get(key) {
+                    Some(delta) => Entry::Changed {
+                        label: Some(key.clone()),
+                        left: object.get(key),
+                        delta,
+                    },
+                    None => Entry::Unchanged {
+                        label: Some(key.clone()),
+                        value: &object[key],
+                    },
+                })
+                .collect();
+            Node::Container {
+                kind: ContainerKind::Object,
+                entries,
+            }
+        }
+        Delta::Array(changes) => {
+            let array = left
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid_target("array"))?;
+            Node::Container {
+                kind: ContainerKind::Array,
+                entries: array_entries(array, changes)?,
+            }
+        }
+        Delta::Unordered { removed, added } => {
+            let removed = removed.iter().map(|item| Entry::Deleted {
+                label: None,
+                value: item.as_ref(),
+            });
+            let added = added.iter().map(|item| Entry::Added {
+                label: None,
+                value: item.as_ref(),
+            });
+            Node::Container {
+                kind: ContainerKind::Unordered,
+                entries: removed.chain(added).collect(),
+            }
+        }
+        Delta::Keyed {
+            key,
+            changes,
+            order,
+        } => {
+            let array = left
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid_target("keyed array"))?;
+            Node::Container {
+                kind: ContainerKind::Keyed,
+                entries: keyed_array_entries(array, key, changes, order.as_ref()),
+            }
+        }
+        Delta::None => Node::None,
+    };
+    Ok(node)
+}
+
+/// The entries of an array by index, where deleted and moved items are shown at their original
+/// index, and any other item at its new index
+fn array_entries<'d, 'a>(
+    array: &'d [Value],
+    changes: &'d [(ArrayDeltaIndex, Delta<'a>)],
+) -> Result<Vec<Entry<'d, 'a>>, JsonDiffPatchError> {
+    let index_map = ArrayIndexMap::new(changes);
+    let mut removed_count = 0;
+    let mut inserted_count = 0;
+    let mut changed_new_indices = HashSet::new();
+    // entries sorted by index, the original indices first
+    let mut entries: Vec<((usize, bool), Entry)> = Vec::new();
+
+    for (index, delta) in changes {
+        let entry = match (index, delta) {
+            (ArrayDeltaIndex::RemovedOrMoved(old_index), delta) => {
+                removed_count += 1;
+                let value =
+                    array
+                        .get(*old_index)
+                        .ok_or(JsonDiffPatchError::IndexOutOfBoundsRemove {
+                            index: *old_index,
+                            length: array.len(),
+                        })?;
+                let label = old_index.to_string();
+                let entry = match delta {
+                    Delta::Moved { new_index, .. } => {
+                        inserted_count += 1;
+                        changed_new_indices.insert(*new_index);
+                        Entry::Moved {
+                            label,
+                            value,
+                            new_index: *new_index,
+                        }
+                    }
+                    _ => Entry::Deleted {
+                        label: Some(label),
+                        value,
+                    },
+                };
+                ((*old_index, false), entry)
+            }
+            (ArrayDeltaIndex::NewOrModified(_), Delta::None) => continue,
+            (ArrayDeltaIndex::NewOrModified(new_index), delta) => {
+                changed_new_indices.insert(*new_index);
+                let left = match delta {
+                    Delta::Added(_) => {
+                        inserted_count += 1;
+                        None
+                    }
+                    _ => Some(
+                        index_map
+                            .old_index(*new_index)
+                            .and_then(|old_index| array.get(old_index))
+                            .ok_or(JsonDiffPatchError::IndexOutOfBoundsModify {
+                                index: *new_index,
+                                length: array.len(),
+                            })?,
+                    ),
+                };
+                let entry = Entry::Changed {
+                    label: Some(new_index.to_string()),
+                    left,
+                    delta,
+                };
+                ((*new_index, true), entry)
+            }
+        };
+        entries.push(entry);
+    }
+
+    // the kept items, at their new index
+    let new_length = (array.len() + inserted_count).saturating_sub(removed_count);
+    for new_index in (0..new_length).filter(|i| !changed_new_indices.contains(i)) {
+        if let Some(value) = index_map
+            .old_index(new_index)
+            .and_then(|old_index| array.get(old_index))
+        {
+            let entry = Entry::Unchanged {
+                label: Some(new_index.to_string()),
+                value,
+            };
+            entries.push(((new_index, true), entry));
+        }
+    }
+
+    entries.sort_by_key(|(position, _)| *position);
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// The entries of a keyed array, in the order of the original items, followed by the added ones,
+/// labeled by key. Unchanged items that the `order` puts elsewhere are shown as moved.
+fn keyed_array_entries<'d, 'a>(
+    array: &'d [Value],
+    key_field: &str,
+    changes: &'d [(String, Delta<'a>)],
+    order: Option<&KeyedOrder>,
+) -> Vec<Entry<'d, 'a>> {
+    let changes_by_key: HashMap<&str, &Delta> = changes
+        .iter()
+        .map(|(key, delta)| (key.as_str(), delta))
+        .collect();
+    let new_indices: HashMap<&str, usize> = order
+        .map(|order| {
+            order
+                .right
+                .iter()
+                .enumerate()
+                .map(|(index, key)| (key.as_str(), index))
+                .collect()
+        })
+        .unwrap_or_default();
+    let label = |key: &str| format!("{}={}", key_field, key);
+
+    let mut entries = Vec::new();
+    let mut shown = HashSet::new();
+    // the index of each kept item in the patched array before it is put in order
+    let mut kept_count = 0;
+    for item in array {
+        let key = item_key(item, key_field);
+        let change = key.as_deref().and_then(|key| changes_by_key.get(key));
+        let kept_index = kept_count;
+        if !matches!(change, Some(Delta::Deleted(_))) {
+            kept_count += 1;
+        }
+        match (change, key) {
+            (Some(delta), key) => {
+                let key = key.expect("changed items have a key");
+                entries.push(Entry::Changed {
+                    label: Some(label(&key)),
+                    left: Some(item),
+                    delta,
+                });
+                shown.insert(key);
+            }
+            (None, Some(key))
+                if new_indices
+                    .get(key.as_str())
+                    .is_some_and(|&new_index| new_index != kept_index) =>
+            {
+                entries.push(Entry::Moved {
+                    label: label(&key),
+                    value: item,
+                    new_index: new_indices[key.as_str()],
+                });
+            }
+            (None, key) => entries.push(Entry::Unchanged {
+                label: key.as_deref().map(label),
+                value: item,
+            }),
+        }
+    }
+    for (key, delta) in changes {
+        if !shown.contains(key) {
+            entries.push(Entry::Changed {
+                label: Some(label(key)),
+                left: None,
+                delta,
+            });
+        }
+    }
+    entries
+}
+
+/// The distance from each entry to the closest changed one, `usize::MAX` when none changed
+pub(crate) fn change_distances(entries: &[Entry]) -> Vec<usize> {
+    let mut distances = vec![usize::MAX; entries.len()];
+    let mut last_change = None;
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.is_changed() {
+            last_change = Some(index);
+        }
+        if let Some(last_change) = last_change {
+            distances[index] = index - last_change;
+        }
+    }
+    last_change = None;
+    for (index, entry) in entries.iter().enumerate().rev() {
+        if entry.is_changed() {
+            last_change = Some(index);
+        }
+        if let Some(last_change) = last_change {
+            distances[index] = distances[index].min(last_change - index);
+        }
+    }
+    distances
+}
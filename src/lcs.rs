@@ -15,103 +15,306 @@ use serde_json::Value;
 ///
 /// ## Note
 /// The function may return different LCSs for the same pair of strings depending on the
-/// order of the inputs and the nature of the sequences. This is due to the way the dynamic
-/// programming algorithm resolves ties when multiple common subsequences of the same length
-/// exist. The order of the input strings can influence the specific path taken through the
-/// DP table, resulting in different valid LCS outputs.
+/// order of the inputs and the nature of the sequences, when multiple common subsequences of the
+/// same length exist.
 ///
 ///  For example:
-/// `longest_common_subsequence("hello, world!", "world, hello!")` returns `"hello!"`
-/// but
-/// `longest_common_subsequence("world, hello!", "hello, world!")` returns `"world!"`
-///
-/// This difference arises because the dynamic programming table is filled differently based
-/// on the input order, leading to different tie-breaking decisions and thus different LCS results.
+/// `longest_common_subsequence("hello, world!", "world, hello!")` may return `"hello!"`
+/// while
+/// `longest_common_subsequence("world, hello!", "hello, world!")` may return `"world!"`
 pub fn longest_common_subsequence(
     first_seq: &[Value],
     second_seq: &[Value],
 ) -> Vec<(usize, usize)> {
-    let lcs_lengths = initialize_lcs_lengths(first_seq, second_seq);
+    longest_common_subsequence_by(first_seq.len(), second_seq.len(), |i, j| {
+        first_seq[i] == second_seq[j]
+    })
+}
+
+/// Computes the longest common subsequence of two sequences given by their lengths, where
+/// `matches(i, j)` tells whether the `i`-th item of the first sequence matches the `j`-th item of
+/// the second one.
+///
+/// Returns the pairs of matched indices, in increasing order.
+///
+/// This is the linear space variant of Myers' O(ND) algorithm: it runs in O((N+M)·D) time, where
+/// `D` is the number of differences, and only needs O(N+M) memory.
+pub fn longest_common_subsequence_by(
+    first_seq_len: usize,
+    second_seq_len: usize,
+    matches: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let max_d = max_d(first_seq_len, second_seq_len);
+    let mut forward = V::new(max_d);
+    let mut backward = V::new(max_d);
+    let mut lcs_indices = Vec::new();
+
+    // ranges still to be compared, split around their middle snake until they are trivial
+    let mut pending = vec![(0..first_seq_len, 0..second_seq_len)];
+    while let Some((mut first_range, mut second_range)) = pending.pop() {
+        // items of a common prefix or suffix are always part of the subsequence
+        while first_range.start < first_range.end
+            && second_range.start < second_range.end
+            && matches(first_range.start, second_range.start)
+        {
+            lcs_indices.push((first_range.start, second_range.start));
+            first_range.start += 1;
+            second_range.start += 1;
+        }
+        while first_range.start < first_range.end
+            && second_range.start < second_range.end
+            && matches(first_range.end - 1, second_range.end - 1)
+        {
+            first_range.end -= 1;
+            second_range.end -= 1;
+            lcs_indices.push((first_range.end, second_range.end));
+        }
+
+        if first_range.is_empty() || second_range.is_empty() {
+            continue;
+        }
+        if let Some((first_split, second_split)) = find_middle_snake(
+            first_range.clone(),
+            second_range.clone(),
+            &mut forward,
+            &mut backward,
+            &matches,
+        ) {
+            pending.push((
+                first_range.start..first_split,
+                second_range.start..second_split,
+            ));
+            pending.push((first_split..first_range.end, second_split..second_range.end));
+        }
+    }
 
-    reconstruct_lcs(first_seq, second_seq, &lcs_lengths)
+    lcs_indices.sort_unstable();
+    lcs_indices
 }
 
-fn initialize_lcs_lengths(first_seq: &[Value], second_seq: &[Value]) -> Vec<Vec<usize>> {
-    let first_seq_len = first_seq.len();
-    let second_seq_len = second_seq.len();
+fn max_d(first_seq_len: usize, second_seq_len: usize) -> usize {
+    (first_seq_len + second_seq_len).div_ceil(2) + 1
+}
 
-    let mut lcs_lengths = vec![vec![0; second_seq_len + 1]; first_seq_len + 1];
+/// The furthest reaching x coordinate of each diagonal `k`, where `k` may be negative
+struct V {
+    offset: isize,
+    values: Vec<usize>,
+}
+
+impl V {
+    fn new(max_d: usize) -> Self {
+        Self {
+            offset: max_d as isize + 1,
+            values: vec![0; 2 * max_d + 3],
+        }
+    }
+}
+
+impl std::ops::Index<isize> for V {
+    type Output = usize;
 
-    // Populate the LCS lengths table
-    (1..=first_seq_len).for_each(|i| {
-        (1..=second_seq_len).for_each(|j| {
-            lcs_lengths[i][j] = if first_seq[i - 1] == second_seq[j - 1] {
-                lcs_lengths[i - 1][j - 1] + 1
+    fn index(&self, k: isize) -> &usize {
+        &self.values[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.values[(k + self.offset) as usize]
+    }
+}
+
+/// Finds the start of the middle snake of an optimal edit path between the two ranges, which
+/// splits the problem in two halves that can be solved independently.
+fn find_middle_snake(
+    first_range: std::ops::Range<usize>,
+    second_range: std::ops::Range<usize>,
+    forward: &mut V,
+    backward: &mut V,
+    matches: &impl Fn(usize, usize) -> bool,
+) -> Option<(usize, usize)> {
+    let n = first_range.len();
+    let m = second_range.len();
+    let first = |x: usize| first_range.start + x;
+    let second = |y: usize| second_range.start + y;
+
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    forward[1] = 0;
+    backward[1] = 0;
+
+    for d in 0..max_d(n, m) as isize {
+        // extend the forward paths, from the start of the ranges
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && forward[k - 1] < forward[k + 1]) {
+                forward[k + 1]
             } else {
-                lcs_lengths[i - 1][j].max(lcs_lengths[i][j - 1])
+                forward[k - 1] + 1
             };
-        });
-    });
+            let mut y = (x as isize - k) as usize;
+            let (snake_x, snake_y) = (x, y);
+            while x < n && y < m && matches(first(x), second(y)) {
+                x += 1;
+                y += 1;
+            }
+            forward[k] = x;
 
-    lcs_lengths
-}
+            if odd && (k - delta).abs() < d && forward[k] + backward[-(k - delta)] >= n {
+                return Some((first(snake_x), second(snake_y)));
+            }
+        }
 
-fn reconstruct_lcs(
-    first_seq_chars: &[Value],
-    second_seq_chars: &[Value],
-    lcs_lengths: &[Vec<usize>],
-) -> Vec<(usize, usize)> {
-    let mut lcs_indices = Vec::new();
-    let mut i = first_seq_chars.len();
-    let mut j = second_seq_chars.len();
-    while i > 0 && j > 0 {
-        if first_seq_chars[i - 1] == second_seq_chars[j - 1] {
-            lcs_indices.push((i - 1, j - 1));
-            i -= 1;
-            j -= 1;
-        } else if lcs_lengths[i - 1][j] >= lcs_lengths[i][j - 1] {
-            i -= 1;
-        } else {
-            j -= 1;
+        // extend the backward paths, from the end of the ranges
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && backward[k - 1] < backward[k + 1]) {
+                backward[k + 1]
+            } else {
+                backward[k - 1] + 1
+            };
+            let mut y = (x as isize - k) as usize;
+            while x < n && y < m && matches(first(n - x - 1), second(m - y - 1)) {
+                x += 1;
+                y += 1;
+            }
+            backward[k] = x;
+
+            if !odd && (k - delta).abs() <= d && backward[k] + forward[-(k - delta)] >= n {
+                return Some((first(n - x), second(m - y)));
+            }
         }
     }
 
-    lcs_indices.reverse();
-    lcs_indices
+    None
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-
-//     macro_rules! longest_common_subsequence_tests {
-//         ($($name:ident: $test_case:expr,)*) => {
-//             $(
-//                 #[test]
-//                 fn $name() {
-//                     let (first_seq, second_seq, expected_lcs) = $test_case;
-//                     assert_eq!(longest_common_subsequence(&first_seq, &second_seq), expected_lcs);
-//                 }
-//             )*
-//         };
-//     }
-
-//     longest_common_subsequence_tests! {
-//         empty_case: ("", "", ""),
-//         one_empty: ("", "abcd", ""),
-//         identical_strings: ("abcd", "abcd", "abcd"),
-//         completely_different: ("abcd", "efgh", ""),
-//         single_character: ("a", "a", "a"),
-//         different_length: ("abcd", "abc", "abc"),
-//         special_characters: ("$#%&", "#@!%", "#%"),
-//         long_strings: ("abcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefgh",
-//                       "bcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefgha",
-//                       "bcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefgh"),
-//         unicode_characters: ("你好，世界", "再见，世界", "，世界"),
-//         spaces_and_punctuation_0: ("hello, world!", "world, hello!", "hello!"),
-//         spaces_and_punctuation_1: ("hello, world!", "world, hello!", "hello!"), // longest_common_subsequence is not symmetric
-//         random_case_1: ("abcdef", "xbcxxxe", "bce"),
-//         random_case_2: ("xyz", "abc", ""),
-//         random_case_3: ("abracadabra", "avadakedavra", "aaadara"),
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<Value> {
+        text.chars().map(|c| Value::String(c.to_string())).collect()
+    }
+
+    /// Length of the longest common subsequence, from the full dynamic programming table
+    fn lcs_length(first_seq: &[Value], second_seq: &[Value]) -> usize {
+        let mut lengths = vec![vec![0; second_seq.len() + 1]; first_seq.len() + 1];
+        for i in 1..=first_seq.len() {
+            for j in 1..=second_seq.len() {
+                lengths[i][j] = if first_seq[i - 1] == second_seq[j - 1] {
+                    lengths[i - 1][j - 1] + 1
+                } else {
+                    lengths[i - 1][j].max(lengths[i][j - 1])
+                };
+            }
+        }
+        lengths[first_seq.len()][second_seq.len()]
+    }
+
+    fn assert_longest_common_subsequence(first_seq: &[Value], second_seq: &[Value]) {
+        let lcs_indices = longest_common_subsequence(first_seq, second_seq);
+        assert_eq!(
+            lcs_indices.len(),
+            lcs_length(first_seq, second_seq),
+            "{:?} / {:?}",
+            first_seq,
+            second_seq
+        );
+        for window in lcs_indices.windows(2) {
+            assert!(window[0].0 < window[1].0 && window[0].1 < window[1].1);
+        }
+        for &(i, j) in &lcs_indices {
+            assert_eq!(first_seq[i], second_seq[j]);
+        }
+    }
+
+    macro_rules! longest_common_subsequence_tests {
+        ($($name:ident: $test_case:expr,)*) => {
+            $(
+                #[test]
+                fn $name() {
+                    let (first_seq, second_seq, expected_length) = $test_case;
+                    let (first_seq, second_seq) = (chars(first_seq), chars(second_seq));
+                    assert_eq!(lcs_length(&first_seq, &second_seq), expected_length);
+                    assert_longest_common_subsequence(&first_seq, &second_seq);
+                }
+            )*
+        };
+    }
+
+    longest_common_subsequence_tests! {
+        empty_case: ("", "", 0),
+        one_empty: ("", "abcd", 0),
+        identical_strings: ("abcd", "abcd", 4),
+        completely_different: ("abcd", "efgh", 0),
+        single_character: ("a", "a", 1),
+        different_length: ("abcd", "abc", 3),
+        special_characters: ("$#%&", "#@!%", 2),
+        long_strings: ("abcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefgh",
+                      "bcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefghabcdefgha",
+                      79),
+        unicode_characters: ("你好，世界", "再见，世界", 3),
+        spaces_and_punctuation: ("hello, world!", "world, hello!", 6),
+        random_case_1: ("abcdef", "xbcxxxe", 3),
+        random_case_2: ("xyz", "abc", 0),
+        random_case_3: ("abracadabra", "avadakedavra", 7),
+    }
+
+    #[test]
+    fn test_pseudo_random_sequences() {
+        // a small linear congruential generator, to get reproducible sequences
+        let mut seed: u64 = 42;
+        let mut next = move |bound: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..500 {
+            let alphabet = next(4) + 1;
+            let first_seq: Vec<Value> = (0..next(20)).map(|_| next(alphabet).into()).collect();
+            let second_seq: Vec<Value> = (0..next(20)).map(|_| next(alphabet).into()).collect();
+            assert_longest_common_subsequence(&first_seq, &second_seq);
+        }
+    }
+
+    #[test]
+    fn test_large_sequences() {
+        let first_seq: Vec<Value> = (0..20_000).map(Value::from).collect();
+        let second_seq: Vec<Value> = (0..20_000)
+            .filter(|i| i % 100 != 0)
+            .chain(20_000..20_050)
+            .map(Value::from)
+            .collect();
+        assert_eq!(
+            longest_common_subsequence(&first_seq, &second_seq).len(),
+            19_800
+        );
+    }
+
+    #[test]
+    fn test_large_sequences_with_moves() {
+        let diffpatcher = crate::DiffPatcher::new(Some(crate::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                detect_move: Some(true),
+                algorithm: Some(crate::array_matchers::ArrayDiffAlgorithm::Patience),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        // the second half of the items is reversed and moved before the first one
+        let left = Value::from((0..20_000).collect::<Vec<_>>());
+        let right = Value::from((10_000..20_000).rev().chain(0..10_000).collect::<Vec<_>>());
+
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+        let crate::types::Delta::Array(changes) = &delta else {
+            panic!("expected an array delta, got {:?}", delta);
+        };
+        assert_eq!(changes.len(), 10_000);
+        assert!(changes
+            .iter()
+            .all(|(_, delta)| matches!(delta, crate::types::Delta::Moved { .. })));
+        assert_eq!(diffpatcher.patch(&left, delta), Some(right));
+    }
+}
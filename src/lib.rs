@@ -1,12 +1,19 @@
+pub mod array_matchers;
 pub mod context;
 pub mod diffpatcher;
 pub mod errors;
+pub mod formatters;
 pub mod lcs;
+pub mod merge_patch;
+pub mod path;
 pub mod pipeline;
 pub mod processor;
+pub mod similarity;
+mod subtree_hash;
 pub mod types;
 
 pub use diffpatcher::DiffPatcher;
+pub use errors::JsonDiffPatchError;
 pub use types::{Delta, Options};
 
 use std::sync::OnceLock;
@@ -36,3 +43,32 @@ pub fn reverse<'a>(delta: &Delta<'a>) -> Option<Delta<'a>> {
     let instance = DEFAULT_INSTANCE.get_or_init(|| DiffPatcher::new(None));
     instance.reverse(delta)
 }
+
+pub fn try_diff<'a>(
+    left: &'a serde_json::Value,
+    right: &'a serde_json::Value,
+) -> Result<Option<Delta<'a>>, JsonDiffPatchError> {
+    let instance = DEFAULT_INSTANCE.get_or_init(|| DiffPatcher::new(None));
+    instance.try_diff(left, right)
+}
+
+pub fn try_patch(
+    left: &serde_json::Value,
+    delta: Delta,
+) -> Result<Option<serde_json::Value>, JsonDiffPatchError> {
+    let instance = DEFAULT_INSTANCE.get_or_init(|| DiffPatcher::new(None));
+    instance.try_patch(left, delta)
+}
+
+pub fn try_unpatch(
+    right: &serde_json::Value,
+    delta: &Delta,
+) -> Result<Option<serde_json::Value>, JsonDiffPatchError> {
+    let instance = DEFAULT_INSTANCE.get_or_init(|| DiffPatcher::new(None));
+    instance.try_unpatch(right, delta)
+}
+
+pub fn try_reverse<'a>(delta: &Delta<'a>) -> Result<Option<Delta<'a>>, JsonDiffPatchError> {
+    let instance = DEFAULT_INSTANCE.get_or_init(|| DiffPatcher::new(None));
+    instance.try_reverse(delta)
+}
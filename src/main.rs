@@ -1,5 +1,7 @@
 use jsondiffpatch::{
-    diff, patch,
+    diff,
+    formatters::console,
+    patch,
     types::{ArrayDeltaIndex, Delta},
 };
 use serde_json::json;
@@ -38,7 +40,7 @@ fn main() {
     println!("Right: {}", right);
 
     if let Some(delta) = diff(&left, &right) {
-        println!("Delta: {:#?}", delta);
+        print!("Delta:\n{}", console::format(&left, &delta).unwrap());
         // try apply the delta
         let patched = patch(&left, delta).unwrap();
         println!("Patched: {:?}", patched);
@@ -61,7 +63,7 @@ fn main() {
     println!("Right: {}", right_array);
 
     if let Some(delta) = diff(&left_array, &right_array) {
-        println!("Delta: {:?}", delta);
+        print!("Delta:\n{}", console::format(&left_array, &delta).unwrap());
         let patched = patch(&left_array, delta).unwrap();
         println!("Patched: {:?}", patched);
         assert_eq!(patched, right_array);
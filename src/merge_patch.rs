@@ -0,0 +1,398 @@
+//! [RFC 7386](https://www.rfc-editor.org/rfc/rfc7386) JSON Merge Patch generation and
+//! application.
+//!
+//! A merge patch replaces arrays as a whole and removes the properties it sets to `null`, so some
+//! changes cannot be expressed faithfully: these are reported along with the generated patch.
+
+use crate::errors::JsonDiffPatchError;
+use crate::path::pointer_child;
+use crate::types::Delta;
+use serde_json::{Map, Value};
+
+/// A merge patch, along with the changes it does not carry faithfully
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergePatch {
+    pub patch: Value,
+    pub lossy_changes: Vec<LossyChange>,
+}
+
+impl MergePatch {
+    /// Whether applying the patch to the original document gives exactly the changed document
+    /// and nothing is replaced beyond the changes themselves
+    pub fn is_lossless(&self) -> bool {
+        self.lossy_changes.is_empty()
+    }
+
+    fn report(&mut self, path: String, kind: LossyChangeKind) {
+        self.lossy_changes.push(LossyChange { path, kind });
+    }
+
+    /// Reports the nulls that merging `value` at `path` would turn into removals: the value
+    /// itself, and the values of properties of nested objects
+    fn report_nulls(&mut self, path: &str, value: &Value) {
+        let mut pending = vec![(path.to_string(), value)];
+        while let Some((path, value)) = pending.pop() {
+            match value {
+                Value::Null => self.report(path, LossyChangeKind::NullValue),
+                Value::Object(object) => {
+                    for (key, value) in object {
+                        pending.push((pointer_child(&path, key), value));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A change that a merge patch does not carry faithfully, at a JSON pointer
+#[derive(Debug, Clone, PartialEq)]
+pub struct LossyChange {
+    pub path: String,
+    pub kind: LossyChangeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LossyChangeKind {
+    /// the array changed, and the patch replaces it as a whole; applying the patch still gives
+    /// the changed array, but discards concurrent changes to it
+    ArrayReplaced,
+    /// the value is set to `null`, which the patch expresses as a removal of the property
+    NullValue,
+}
+
+/// The merge patch turning `left` into `right`
+pub fn diff(left: &Value, right: &Value) -> MergePatch {
+    let delta = crate::diff(left, right);
+    match delta {
+        Some(delta) => from_delta(left, &delta).expect("a delta fits the value it comes from"),
+        None => unchanged(left),
+    }
+}
+
+/// The merge patch applying the delta to `left`, which the delta must fit
+pub fn from_delta(left: &Value, delta: &Delta) -> Result<MergePatch, JsonDiffPatchError> {
+    if let Delta::None = delta {
+        return Ok(unchanged(left));
+    }
+
+    let mut merge_patch = MergePatch {
+        patch: Value::Object(Map::new()),
+        lossy_changes: Vec::new(),
+    };
+    // deltas left to turn into the patch, with the path of the value they apply to
+    let mut pending = vec![(Vec::<String>::new(), Some(left), delta)];
+    while let Some((segments, left, delta)) = pending.pop() {
+        let path = || {
+            segments
+                .iter()
+                .fold(String::new(), |path, name| pointer_child(&path, name))
+        };
+        let new_value = match delta {
+            Delta::Object(changes) => {
+                insert(&mut merge_patch.patch, &segments, None);
+                for (key, child) in changes {
+                    let mut child_segments = segments.clone();
+                    child_segments.push(key.clone());
+                    pending.push((child_segments, left.and_then(|left| left.get(key)), child));
+                }
+                continue;
+            }
+            Delta::Added(new_value) | Delta::Modified(_, new_value) => new_value.as_ref().clone(),
+            Delta::Deleted(_) => {
+                // a removed document can only become null
+                if segments.is_empty() {
+                    merge_patch.report(path(), LossyChangeKind::NullValue);
+                }
+                insert(&mut merge_patch.patch, &segments, Some(Value::Null));
+                continue;
+            }
+            Delta::Array(_) | Delta::Unordered { .. } | Delta::Keyed { .. } => {
+                merge_patch.report(path(), LossyChangeKind::ArrayReplaced);
+                patched(left, delta)?
+            }
+            Delta::TextDiff(_) => patched(left, delta)?,
+            Delta::Moved { .. } => {
+                return Err(JsonDiffPatchError::InvalidPatch(
+                    "a move can only be applied to an item of an array".to_string(),
+                ));
+            }
+            Delta::None => continue,
+        };
+
+        // the nulls of the value are removals once merged, except for a whole document
+        if !(segments.is_empty() && new_value.is_null()) {
+            merge_patch.report_nulls(&path(), &new_value);
+        }
+        let new_value = match left {
+            Some(left) => replacement(left, new_value),
+            None => new_value,
+        };
+        insert(&mut merge_patch.patch, &segments, Some(new_value));
+    }
+    Ok(merge_patch)
+}
+
+/// Applies a merge patch to a copy of `target`
+pub fn apply(target: &Value, patch: &Value) -> Value {
+    let mut result = target.clone();
+    apply_in_place(&mut result, patch);
+    result
+}
+
+/// Applies a merge patch to `target`, following the algorithm of RFC 7386
+pub fn apply_in_place(target: &mut Value, patch: &Value) {
+    let mut pending = vec![(target, patch)];
+    while let Some((target, patch)) = pending.pop() {
+        let Value::Object(patch) = patch else {
+            *target = patch.clone();
+            continue;
+        };
+        if !target.is_object() {
+            *target = Value::Object(Map::new());
+        }
+        let target = target.as_object_mut().expect("target is an object");
+
+        for (key, value) in patch {
+            match value {
+                Value::Null => {
+                    target.remove(key);
+                }
+                // merged below
+                Value::Object(_) => {
+                    target.entry(key.clone()).or_insert(Value::Null);
+                }
+                value => {
+                    target.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        for (key, value) in target.iter_mut() {
+            if let Some(value_patch @ Value::Object(_)) = patch.get(key) {
+                pending.push((value, value_patch));
+            }
+        }
+    }
+}
+
+/// The patch of a document that does not change
+fn unchanged(left: &Value) -> MergePatch {
+    MergePatch {
+        // an empty patch would turn any other value into an empty object
+        patch: if left.is_object() {
+            Value::Object(Map::new())
+        } else {
+            left.clone()
+        },
+        lossy_changes: Vec::new(),
+    }
+}
+
+fn patched(left: Option<&Value>, delta: &Delta) -> Result<Value, JsonDiffPatchError> {
+    let invalid_target = || JsonDiffPatchError::InvalidPatchToTarget {
+        patch: "merge patch".to_string(),
+    };
+    crate::try_patch(left.ok_or_else(invalid_target)?, delta.clone())?.ok_or_else(invalid_target)
+}
+
+/// The patch replacing `left` with `value`: an object is merged into an object rather than
+/// replacing it, so the properties of `left` that `value` does not have are set to `null`
+fn replacement(left: &Value, mut value: Value) -> Value {
+    let mut pending = vec![(left, &mut value)];
+    while let Some((left, value)) = pending.pop() {
+        let (Value::Object(left), Value::Object(object)) = (left, value) else {
+            continue;
+        };
+        for key in left.keys() {
+            if !object.contains_key(key) {
+                object.insert(key.clone(), Value::Null);
+            }
+        }
+        for (key, value) in object.iter_mut() {
+            if let Some(left) = left.get(key) {
+                pending.push((left, value));
+            }
+        }
+    }
+    value
+}
+
+/// Sets `value` at the path given by `segments` in the patch, creating the objects on the way,
+/// or only creates the objects when `value` is `None`
+fn insert(patch: &mut Value, segments: &[String], value: Option<Value>) {
+    let mut current = patch;
+    for segment in segments {
+        if !current.is_object() {
+            *current = Value::Object(Map::new());
+        }
+        current = current
+            .as_object_mut()
+            .expect("current is an object")
+            .entry(segment.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+    }
+    if let Some(value) = value {
+        *current = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply() {
+        // examples of RFC 7386, appendix A
+        let cases = [
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+
+        for (target, patch, expected) in cases {
+            assert_eq!(apply(&target, &patch), expected, "{} + {}", target, patch);
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let left = json!({
+            "title": "Goodbye!",
+            "author": {"givenName": "John", "familyName": "Doe"},
+            "tags": ["example", "sample"],
+            "content": "This will be unchanged"
+        });
+        let right = json!({
+            "title": "Hello!",
+            "author": {"givenName": "John"},
+            "tags": ["example"],
+            "content": "This will be unchanged",
+            "phoneNumber": "+01-123-456-7890"
+        });
+
+        let merge_patch = diff(&left, &right);
+        assert_eq!(
+            merge_patch.patch,
+            json!({
+                "title": "Hello!",
+                "phoneNumber": "+01-123-456-7890",
+                "author": {"familyName": null},
+                "tags": ["example"]
+            })
+        );
+        assert_eq!(
+            merge_patch.lossy_changes,
+            vec![LossyChange {
+                path: "/tags".to_string(),
+                kind: LossyChangeKind::ArrayReplaced
+            }]
+        );
+        assert_eq!(apply(&left, &merge_patch.patch), right);
+
+        for (left, right) in [
+            (json!({"a": 1}), json!({"a": 1})),
+            (json!(1), json!(1)),
+            (json!({"a": 1}), json!([1])),
+            (json!(1), json!({"a": {"b": 1}})),
+            (json!({"a": 1}), json!({"b": {"c": {}}})),
+        ] {
+            let merge_patch = diff(&left, &right);
+            assert!(merge_patch.is_lossless(), "{:?}", merge_patch);
+            assert_eq!(apply(&left, &merge_patch.patch), right);
+        }
+    }
+
+    #[test]
+    fn test_lossy_nulls() {
+        let left = json!({"a": 1, "b": {"c": 1}});
+        let right = json!({"a": null, "b": {"c": 1, "d": {"e": null, "f": [null]}}});
+
+        let merge_patch = diff(&left, &right);
+        let mut lossy_paths: Vec<_> = merge_patch
+            .lossy_changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.kind))
+            .collect();
+        lossy_paths.sort_unstable_by_key(|(path, _)| *path);
+        assert_eq!(
+            lossy_paths,
+            vec![
+                ("/a", LossyChangeKind::NullValue),
+                ("/b/d/e", LossyChangeKind::NullValue)
+            ]
+        );
+        assert_eq!(
+            apply(&left, &merge_patch.patch),
+            json!({"b": {"c": 1, "d": {"f": [null]}}})
+        );
+
+        // objects replacing objects are merged into them
+        let left = json!({"a": 1, "b": {"c": {"d": 1, "e": 1}}});
+        let delta = Delta::Object(
+            [(
+                "b".to_string(),
+                Delta::Modified(
+                    None,
+                    std::borrow::Cow::Owned(json!({"c": {"d": 2}, "f": 1})),
+                ),
+            )]
+            .into(),
+        );
+        let merge_patch = from_delta(&left, &delta).unwrap();
+        assert_eq!(
+            merge_patch.patch,
+            json!({"b": {"c": {"d": 2, "e": null}, "f": 1}})
+        );
+        assert!(merge_patch.is_lossless());
+        assert_eq!(
+            apply(&left, &merge_patch.patch),
+            crate::patch(&left, delta).unwrap()
+        );
+
+        assert!(diff(&json!({"a": 1}), &json!(null)).is_lossless());
+        let merge_patch = from_delta(&json!({"a": 1}), &Delta::Deleted(None)).unwrap();
+        assert_eq!(merge_patch.patch, Value::Null);
+        assert!(!merge_patch.is_lossless());
+    }
+}
@@ -0,0 +1,126 @@
+//! Paths of the values being diffed, and patterns selecting them in options.
+
+use std::sync::Arc;
+
+/// The last segment (object key or array index) of the path of a value, linked to the path of its
+/// parent, so that the path of a child is built without copying the path of its parent
+#[derive(Debug)]
+pub(crate) struct PathSegment {
+    parent: Option<Arc<PathSegment>>,
+    name: String,
+}
+
+impl PathSegment {
+    pub fn new(parent: Option<Arc<PathSegment>>, name: &str) -> Arc<Self> {
+        Arc::new(Self {
+            parent,
+            name: name.to_string(),
+        })
+    }
+
+    /// The segments of the path, from the root
+    pub fn segments(last: Option<&Arc<PathSegment>>) -> Vec<String> {
+        let mut segments = Vec::new();
+        let mut current = last;
+        while let Some(segment) = current {
+            segments.push(segment.name.clone());
+            current = segment.parent.as_ref();
+        }
+        segments.reverse();
+        segments
+    }
+}
+
+/// Whether a path (given by its segments) matches a pattern written as a JSON pointer, where a
+/// `*` segment matches any object key or array index, e.g. `/users/*/tags`. The empty pattern
+/// matches the root.
+pub fn path_matches(pattern: &str, path: &[String]) -> bool {
+    if pattern.is_empty() {
+        return path.is_empty();
+    }
+    let Some(pattern) = pattern.strip_prefix('/') else {
+        return false;
+    };
+
+    let pattern_segments: Vec<&str> = pattern.split('/').collect();
+    pattern_segments.len() == path.len()
+        && pattern_segments
+            .iter()
+            .zip(path)
+            .all(|(pattern_segment, segment)| {
+                *pattern_segment == "*"
+                    || pattern_segment.replace("~1", "/").replace("~0", "~") == *segment
+            })
+}
+
+/// The segments of a JSON pointer, `None` when it is not a valid one
+pub fn parse_pointer(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    pointer
+        .strip_prefix('/')?
+        .split('/')
+        .map(|segment| {
+            let mut name = String::with_capacity(segment.len());
+            let mut chars = segment.chars();
+            while let Some(c) = chars.next() {
+                if c != '~' {
+                    name.push(c);
+                    continue;
+                }
+                match chars.next()? {
+                    '0' => name.push('~'),
+                    '1' => name.push('/'),
+                    _ => return None,
+                }
+            }
+            Some(name)
+        })
+        .collect()
+}
+
+/// The JSON pointer to a child of the value at `pointer`, escaping `~` and `/` in its name
+pub fn pointer_child(pointer: &str, name: &str) -> String {
+    format!("{}/{}", pointer, name.replace('~', "~0").replace('/', "~1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_path_matches() {
+        let path = |segments: &[&str]| -> Vec<String> {
+            segments.iter().map(|segment| segment.to_string()).collect()
+        };
+
+        assert!(path_matches("", &path(&[])));
+        assert!(!path_matches("", &path(&["tags"])));
+        assert!(path_matches("/tags", &path(&["tags"])));
+        assert!(!path_matches("/tags", &path(&["users", "tags"])));
+        assert!(path_matches(
+            "/users/*/tags",
+            &path(&["users", "3", "tags"])
+        ));
+        assert!(path_matches(
+            "/users/*/tags",
+            &path(&["users", "bob", "tags"])
+        ));
+        assert!(!path_matches("/users/*/tags", &path(&["users", "tags"])));
+        assert!(path_matches("/a~1b/c~0d", &path(&["a/b", "c~d"])));
+        assert!(!path_matches("tags", &path(&["tags"])));
+        assert_eq!(pointer_child("/a", "b/c~d"), "/a/b~1c~0d");
+        assert_eq!(parse_pointer("/a/b~1c~0d"), Some(path(&["a", "b/c~d"])));
+        assert_eq!(parse_pointer("/a//"), Some(path(&["a", "", ""])));
+        assert_eq!(parse_pointer(""), Some(path(&[])));
+        assert_eq!(parse_pointer("a"), None);
+        assert_eq!(parse_pointer("/a~2"), None);
+        assert_eq!(parse_pointer("/a~"), None);
+        assert!(path_matches(&pointer_child("", "a/b"), &path(&["a/b"])));
+
+        let last = PathSegment::new(Some(PathSegment::new(None, "users")), "0");
+        assert_eq!(PathSegment::segments(Some(&last)), path(&["users", "0"]));
+        assert_eq!(PathSegment::segments(None), path(&[]));
+    }
+}
@@ -1,9 +1,208 @@
+use crate::array_matchers::{ArrayDiffAlgorithm, ArrayItems};
 use crate::context::{DiffContext, FilterContext};
 use crate::errors::JsonDiffPatchError;
 use crate::lcs;
-use crate::types::{ArrayDeltaIndex, Delta};
+use crate::path::path_matches;
+use crate::similarity::Leaves;
+use crate::subtree_hash::value_hash;
+use crate::types::{ArrayDeltaIndex, Delta, KeyedOrder};
 use serde_json::Value;
-use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Decides whether an item of the left array and an item of the right array are the same item,
+/// possibly with a modified content.
+///
+/// Objects and arrays are matched by their `object_hash` when it gives one for both items, any
+/// other pair of items is matched by equality.
+struct ItemMatcher<'a> {
+    context: DiffContext<'a>,
+    left: &'a [Value],
+    right: &'a [Value],
+    left_hashes: Vec<Option<String>>,
+    right_hashes: Vec<Option<String>>,
+    /// Whether objects and arrays all get the same key, as their hashes would not follow
+    /// `matches`: when a `property_filter` ignores some of their properties, or when only some of
+    /// them have an `object_hash` (and the others are matched by equality against them)
+    constant_container_keys: bool,
+    left_keys: OnceCell<Vec<u64>>,
+    right_keys: OnceCell<Vec<u64>>,
+    left_leaves: OnceCell<Vec<OnceCell<Leaves<'a>>>>,
+    right_leaves: OnceCell<Vec<OnceCell<Leaves<'a>>>>,
+}
+
+impl<'a> ItemMatcher<'a> {
+    fn new(context: &DiffContext<'a>, left: &'a [Value], right: &'a [Value]) -> Self {
+        let hashes = |array: &[Value]| -> Vec<Option<String>> {
+            match &context.options().object_hash {
+                Some(object_hash) => array
+                    .iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        if item.is_object() || item.is_array() {
+                            object_hash(item, index)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+
+        let left_hashes = hashes(left);
+        let right_hashes = hashes(right);
+        let (mut hashed, mut unhashed) = (false, false);
+        for (array, hashes) in [(left, &left_hashes), (right, &right_hashes)] {
+            for (item, hash) in array.iter().zip(hashes) {
+                if item.is_object() || item.is_array() {
+                    hashed |= hash.is_some();
+                    unhashed |= hash.is_none();
+                }
+            }
+        }
+        let constant_container_keys =
+            context.options().property_filter.is_some() || (hashed && unhashed);
+
+        Self {
+            context: context.new_property_child(context.left, context.right),
+            left,
+            right,
+            left_hashes,
+            right_hashes,
+            constant_container_keys,
+            left_keys: OnceCell::new(),
+            right_keys: OnceCell::new(),
+            left_leaves: OnceCell::new(),
+            right_leaves: OnceCell::new(),
+        }
+    }
+
+    /// The similarity of two items that did not match, `None` when their `object_hash` tells
+    /// they are different items
+    fn similarity(&self, left_index: usize, right_index: usize) -> Option<f64> {
+        if self
+            .left_hashes
+            .get(left_index)
+            .is_some_and(Option::is_some)
+            && self
+                .right_hashes
+                .get(right_index)
+                .is_some_and(Option::is_some)
+        {
+            return None;
+        }
+        let left_leaves = leaves(&self.left_leaves, self.left, left_index);
+        let right_leaves = leaves(&self.right_leaves, self.right, right_index);
+        Some(left_leaves.similarity(right_leaves))
+    }
+
+    /// Keys of the items, from their `object_hash` when they have one, or from their value, see
+    /// `constant_container_keys`
+    fn keys(&self, array: &[Value], hashes: &[Option<String>]) -> Vec<u64> {
+        array
+            .iter()
+            .enumerate()
+            .map(
+                |(index, item)| match hashes.get(index).and_then(Option::as_ref) {
+                    _ if self.constant_container_keys && (item.is_object() || item.is_array()) => 0,
+                    Some(hash) => {
+                        let mut hasher = DefaultHasher::new();
+                        hash.hash(&mut hasher);
+                        hasher.finish()
+                    }
+                    None => self
+                        .context
+                        .subtree_hash(item)
+                        .unwrap_or_else(|| value_hash(item)),
+                },
+            )
+            .collect()
+    }
+}
+
+/// The leaves of an item, computed once for each item
+fn leaves<'c, 'a>(
+    cache: &'c OnceCell<Vec<OnceCell<Leaves<'a>>>>,
+    array: &'a [Value],
+    index: usize,
+) -> &'c Leaves<'a> {
+    cache.get_or_init(|| array.iter().map(|_| OnceCell::new()).collect())[index]
+        .get_or_init(|| Leaves::new(&array[index]))
+}
+
+/// Pairs up the unmatched items found between consecutive matched items when they are similar
+/// enough, keeping their order
+fn match_similar_items(
+    matcher: &ItemMatcher,
+    matched_pairs: &mut Vec<(usize, usize)>,
+    similarity_threshold: f64,
+) {
+    let mut similar_pairs = Vec::new();
+    let (mut left_start, mut right_start) = (0, 0);
+    let bounds = matched_pairs
+        .iter()
+        .copied()
+        .chain(std::iter::once((matcher.left.len(), matcher.right.len())));
+
+    for (left_end, right_end) in bounds {
+        if left_start < left_end && right_start < right_end {
+            let gap_pairs = lcs::longest_common_subsequence_by(
+                left_end - left_start,
+                right_end - right_start,
+                |i, j| {
+                    matcher
+                        .similarity(left_start + i, right_start + j)
+                        .is_some_and(|similarity| similarity >= similarity_threshold)
+                },
+            );
+            similar_pairs.extend(
+                gap_pairs
+                    .into_iter()
+                    .map(|(i, j)| (left_start + i, right_start + j)),
+            );
+        }
+        (left_start, right_start) = (left_end + 1, right_end + 1);
+    }
+
+    matched_pairs.extend(similar_pairs);
+    matched_pairs.sort_unstable();
+}
+
+impl ArrayItems for ItemMatcher<'_> {
+    fn left_len(&self) -> usize {
+        self.left.len()
+    }
+
+    fn right_len(&self) -> usize {
+        self.right.len()
+    }
+
+    fn matches(&self, left_index: usize, right_index: usize) -> bool {
+        let left_hash = self.left_hashes.get(left_index).and_then(Option::as_ref);
+        let right_hash = self.right_hashes.get(right_index).and_then(Option::as_ref);
+        match (left_hash, right_hash) {
+            (Some(left_hash), Some(right_hash)) => left_hash == right_hash,
+            _ => self.context.child_values_equal(
+                &right_index.to_string(),
+                &self.left[left_index],
+                &self.right[right_index],
+            ),
+        }
+    }
+
+    fn left_key(&self, left_index: usize) -> u64 {
+        self.left_keys
+            .get_or_init(|| self.keys(self.left, &self.left_hashes))[left_index]
+    }
+
+    fn right_key(&self, right_index: usize) -> u64 {
+        self.right_keys
+            .get_or_init(|| self.keys(self.right, &self.right_hashes))[right_index]
+    }
+}
 
 pub fn process_arrays_diff<'a>(
     context: &mut DiffContext<'a>,
@@ -11,7 +210,15 @@ pub fn process_arrays_diff<'a>(
     right: &'a [Value],
     new_children_context: &mut Vec<(String, DiffContext<'a>)>,
 ) -> Result<(), JsonDiffPatchError> {
-    // Check if left is an array
+    if let Some(key_field) = keyed_field(context) {
+        if process_keyed_arrays_diff(context, left, right, &key_field, new_children_context) {
+            return Ok(());
+        }
+    }
+    if is_unordered(context) {
+        process_unordered_arrays_diff(context, left, right);
+        return Ok(());
+    }
 
     let left_array = left;
     let right_array = right;
@@ -30,7 +237,7 @@ pub fn process_arrays_diff<'a>(
             for (index, value) in right_array.iter().enumerate() {
                 array_changes.push((
                     ArrayDeltaIndex::NewOrModified(index),
-                    Delta::added_ref(value),
+                    context.added_delta(value),
                 ));
             }
             context.set_result(Delta::Array(array_changes)).exit();
@@ -42,7 +249,7 @@ pub fn process_arrays_diff<'a>(
             for (index, value) in left_array.iter().enumerate() {
                 array_changes.push((
                     ArrayDeltaIndex::RemovedOrMoved(index),
-                    Delta::deleted_ref(value),
+                    context.deleted_delta(value),
                 ));
             }
             context.set_result(Delta::Array(array_changes)).exit();
@@ -51,88 +258,45 @@ pub fn process_arrays_diff<'a>(
         (_, _) => {}
     }
 
-    // Separate common head
-    let mut common_head = 0;
-    while common_head < len1
-        && common_head < len2
-        && left_array[common_head] == right_array[common_head]
-    {
-        let child_context = DiffContext::new(&left_array[common_head], &right_array[common_head]);
-        new_children_context.push((common_head.to_string(), child_context));
-        common_head += 1;
-    }
+    let algorithm = if context.options().match_by_position.unwrap_or(false) {
+        ArrayDiffAlgorithm::Positional
+    } else {
+        context
+            .options()
+            .arrays
+            .as_ref()
+            .and_then(|opts| opts.algorithm)
+            .unwrap_or_default()
+    };
 
-    // Separate common tail
-    let mut common_tail = 0;
-    while common_tail + common_head < len1
-        && common_tail + common_head < len2
-        && left_array[len1 - 1 - common_tail] == right_array[len2 - 1 - common_tail]
-    {
-        let index1 = len1 - 1 - common_tail;
-        let index2 = len2 - 1 - common_tail;
-        let child_context = DiffContext::new(&left_array[index1], &right_array[index2]);
-        new_children_context.push((index2.to_string(), child_context));
-        common_tail += 1;
-    }
+    let matcher = ItemMatcher::new(context, left_array, right_array);
+    let mut matched_pairs = algorithm.matcher().match_items(&matcher);
 
-    // Handle trivial cases after common head/tail separation
-    if common_head + common_tail == len1 {
-        if len1 == len2 {
-            // arrays are identical
-            context.set_result(Delta::None).exit();
-            return Ok(());
-        }
-        // trivial case, a block was added
-        let mut array_changes = Vec::new();
-        for (index, val) in right_array
-            .iter()
-            .enumerate()
-            .take(len2 - common_tail)
-            .skip(common_head)
-        {
-            array_changes.push((ArrayDeltaIndex::NewOrModified(index), Delta::added_ref(val)));
-        }
-        context.set_result(Delta::Array(array_changes)).exit();
-        return Ok(());
+    let similarity_threshold = context
+        .options()
+        .arrays
+        .as_ref()
+        .and_then(|opts| opts.similarity_threshold);
+    if let Some(similarity_threshold) = similarity_threshold {
+        match_similar_items(&matcher, &mut matched_pairs, similarity_threshold);
     }
 
-    if common_head + common_tail == len2 {
-        // trivial case, a block was removed
-        let mut array_changes = Vec::new();
-        for (index, val) in left_array
-            .iter()
-            .enumerate()
-            .take(len1 - common_tail)
-            .skip(common_head)
-        {
-            array_changes.push((
-                ArrayDeltaIndex::RemovedOrMoved(index),
-                Delta::deleted_ref(val),
-            ));
-        }
-        context.set_result(Delta::Array(array_changes)).exit();
-        return Ok(());
+    // position of each item in the other array, when the items were matched
+    let mut matched_in_left = vec![None; len2];
+    let mut is_removed = vec![true; len1];
+    for &(i, j) in &matched_pairs {
+        matched_in_left[j] = Some(i);
+        is_removed[i] = false;
     }
 
-    // Use LCS algorithm on the trimmed arrays
-    let trimmed1 = &left_array[common_head..len1 - common_tail];
-    let trimmed2 = &right_array[common_head..len2 - common_tail];
-    let lcs_indices = lcs::longest_common_subsequence(trimmed1, trimmed2);
-
     let mut array_changes = Vec::new();
-    let mut removed_items = Vec::new();
-
-    // Find removed items (items in trimmed1 but not in LCS)
-    for i in 0..trimmed1.len() {
-        if !lcs_indices.iter().any(|&(lcs_i, _)| lcs_i == i) {
-            let original_index = i + common_head;
-            removed_items.push(original_index);
-            array_changes.push((
-                ArrayDeltaIndex::RemovedOrMoved(original_index),
-                Delta::deleted_ref(&left_array[original_index]),
-            ));
-        }
-    }
+
+    // Find removed items (items of the left array that were not matched)
+    let removed_items: Vec<usize> = is_removed
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &removed)| removed.then_some(index))
+        .collect();
 
     // Check for move detection
     let detect_move = context
@@ -141,89 +305,106 @@ pub fn process_arrays_diff<'a>(
         .as_ref()
         .and_then(|opts| opts.detect_move)
         .unwrap_or(true);
-    let include_value_on_move = context
-        .options()
-        .arrays
-        .as_ref()
-        .and_then(|opts| opts.include_value_on_move)
-        .unwrap_or(false);
 
-    // Process items in the right array
-    for (j, val) in trimmed2.iter().enumerate() {
-        let original_index2 = j + common_head;
-        let lcs_index = lcs_indices.iter().position(|&(_, lcs_j)| lcs_j == j);
-
-        if lcs_index.is_none() {
-            // Item is added, try to match with a removed item for move detection
-            let mut is_move = false;
-            if detect_move && !removed_items.is_empty() {
-                for (remove_idx, &removed_index) in removed_items.iter().enumerate() {
-                    let trimmed_removed_index = removed_index - common_head;
-                    if trimmed_removed_index < trimmed1.len()
-                        && &trimmed1[trimmed_removed_index] == val
-                    {
-                        // Found a match, convert deletion to move
-                        // Remove the deletion from array_changes
-                        array_changes.retain(|(idx, _)| {
-                            if let ArrayDeltaIndex::RemovedOrMoved(idx_val) = idx {
-                                *idx_val != removed_index
-                            } else {
-                                true
-                            }
-                        });
-
-                        // Add move
-                        let moved_value = if include_value_on_move {
-                            Some(Cow::Borrowed(&left_array[removed_index]))
-                        } else {
-                            None
-                        };
-                        array_changes.push((
-                            ArrayDeltaIndex::RemovedOrMoved(removed_index),
-                            Delta::Moved {
-                                moved_value,
-                                new_index: original_index2,
-                            },
-                        ));
-
-                        // Create child context for nested diff
-                        let child_context = DiffContext::new(
-                            &left_array[removed_index],
-                            &right_array[original_index2],
-                        );
-                        new_children_context.push((original_index2.to_string(), child_context));
+    // the removed items by key, in increasing order, so that an added item is only compared to
+    // the removed items it may match
+    let mut removed_by_key: HashMap<u64, VecDeque<usize>> = HashMap::new();
+    if detect_move {
+        for &removed_index in &removed_items {
+            removed_by_key
+                .entry(matcher.left_key(removed_index))
+                .or_default()
+                .push_back(removed_index);
+        }
+    }
 
-                        removed_items.remove(remove_idx);
-                        is_move = true;
-                        break;
-                    }
-                }
+    // Process items in the right array
+    for (index2, matched_index) in matched_in_left.iter().enumerate() {
+        if let Some(&index1) = matched_index.as_ref() {
+            // Item was matched, check for nested changes
+            if !context.child_values_equal(
+                &index2.to_string(),
+                &left_array[index1],
+                &right_array[index2],
+            ) {
+                // Items are different, create child context for nested diff
+                let child_context = context.new_child(&left_array[index1], &right_array[index2]);
+                new_children_context.push((index2.to_string(), child_context));
             }
+            continue;
+        }
 
-            if !is_move {
-                // Item is truly added
+        // Item is added, try to match with a removed item for move detection: the same item
+        // (possibly modified), or else the most similar one
+        let moved_from = if detect_move {
+            removed_by_key
+                .get_mut(&matcher.right_key(index2))
+                .and_then(|candidates| {
+                    // items moved as similar ones are left in their bucket
+                    let position = candidates.iter().position(|&removed_index| {
+                        is_removed[removed_index] && matcher.matches(removed_index, index2)
+                    })?;
+                    candidates.remove(position)
+                })
+                .or_else(|| {
+                    let similarity_threshold = similarity_threshold?;
+                    removed_items
+                        .iter()
+                        .filter(|&&removed_index| is_removed[removed_index])
+                        .filter_map(|&removed_index| {
+                            let similarity = matcher.similarity(removed_index, index2)?;
+                            (similarity >= similarity_threshold)
+                                .then_some((removed_index, similarity))
+                        })
+                        .max_by(|(index_a, similarity_a), (index_b, similarity_b)| {
+                            // the first removed item wins among equally similar ones
+                            similarity_a
+                                .total_cmp(similarity_b)
+                                .then(index_b.cmp(index_a))
+                        })
+                        .map(|(removed_index, _)| removed_index)
+                })
+        } else {
+            None
+        };
+
+        match moved_from {
+            Some(removed_index) => {
+                is_removed[removed_index] = false;
                 array_changes.push((
-                    ArrayDeltaIndex::NewOrModified(original_index2),
-                    Delta::added_ref(&right_array[original_index2]),
+                    ArrayDeltaIndex::RemovedOrMoved(removed_index),
+                    Delta::Moved {
+                        moved_value: context.moved_value(&left_array[removed_index]),
+                        new_index: index2,
+                    },
                 ));
-            }
-        } else {
-            // Item is in LCS, check for nested changes
-            let lcs_idx = lcs_index.ok_or_else(|| {
-                JsonDiffPatchError::InternalPatchLogicError("lcs_index is none".to_string())
-            })?;
-            let (i, _) = lcs_indices[lcs_idx];
-            let original_index1 = i + common_head;
 
-            if trimmed1[i] != trimmed2[j] {
-                // Items are different, create child context for nested diff
+                // Create child context for nested diff
                 let child_context =
-                    DiffContext::new(&left_array[original_index1], &right_array[original_index2]);
-                new_children_context.push((original_index2.to_string(), child_context));
+                    context.new_child(&left_array[removed_index], &right_array[index2]);
+                new_children_context.push((index2.to_string(), child_context));
+            }
+            None => {
+                // Item is truly added
+                array_changes.push((
+                    ArrayDeltaIndex::NewOrModified(index2),
+                    context.added_delta(&right_array[index2]),
+                ));
             }
         }
     }
 
+    // the removed items that were not moved are deleted
+    for removed_index in removed_items
+        .into_iter()
+        .filter(|&removed_index| is_removed[removed_index])
+    {
+        array_changes.push((
+            ArrayDeltaIndex::RemovedOrMoved(removed_index),
+            context.deleted_delta(&left_array[removed_index]),
+        ));
+    }
+
     // If we have changes, set the result
     if !array_changes.is_empty() || !new_children_context.is_empty() {
         context.set_result(Delta::Array(array_changes)).exit();
@@ -234,25 +415,256 @@ pub fn process_arrays_diff<'a>(
     Ok(())
 }
 
+/// The key of an item of a keyed array, its `key_field` when it has a string one, or the JSON text
+/// of a number or boolean one
+pub(crate) fn item_key(item: &Value, key_field: &str) -> Option<String> {
+    match item.get(key_field)? {
+        Value::String(key) => Some(key.clone()),
+        key @ (Value::Number(_) | Value::Bool(_)) => Some(key.to_string()),
+        _ => None,
+    }
+}
+
+/// The keys of a keyed array delta that are not item keys when serialized
+const KEYED_DELTA_FIELDS: [&str; 3] = ["_t", "_key", "_order"];
+
+/// The field by which the array being diffed is keyed, see `ArrayOptions::keyed_paths`
+fn keyed_field(context: &DiffContext) -> Option<String> {
+    let keyed_paths = context.options().arrays.as_ref()?.keyed_paths.as_ref()?;
+    let path = context.path();
+    keyed_paths
+        .iter()
+        .find(|(pattern, _)| path_matches(pattern, &path))
+        .map(|(_, key_field)| key_field.clone())
+}
+
+/// Diffs two arrays of objects as maps keyed by `key_field`, with the modified items diffed by
+/// children named after their key, and their order recorded when it is not the one the patch
+/// gives by default. Returns `false`, leaving the context untouched, when some item has no key, a
+/// key is repeated (e.g. `1` and `"1"`) or a key is one of the fields of the serialized delta.
+fn process_keyed_arrays_diff<'a>(
+    context: &mut DiffContext<'a>,
+    left: &'a [Value],
+    right: &'a [Value],
+    key_field: &str,
+    new_children_context: &mut Vec<(String, DiffContext<'a>)>,
+) -> bool {
+    let index_by_key = |array: &'a [Value]| {
+        let mut indices = HashMap::with_capacity(array.len());
+        for (index, item) in array.iter().enumerate() {
+            let key = item_key(item, key_field)?;
+            if KEYED_DELTA_FIELDS.contains(&key.as_str()) || indices.insert(key, index).is_some() {
+                return None;
+            }
+        }
+        Some(indices)
+    };
+    let (Some(left_indices), Some(right_indices)) = (index_by_key(left), index_by_key(right))
+    else {
+        return false;
+    };
+
+    let mut changes = Vec::new();
+    for item in left {
+        let key = item_key(item, key_field).expect("left items have keys");
+        if !right_indices.contains_key(&key) {
+            changes.push((key, context.deleted_delta(item)));
+        }
+    }
+    for item in right {
+        let key = item_key(item, key_field).expect("right items have keys");
+        match left_indices.get(&key) {
+            Some(&left_index) => {
+                if !context.child_values_equal(&key, &left[left_index], item) {
+                    new_children_context.push((key, context.new_child(&left[left_index], item)));
+                }
+            }
+            None => changes.push((key, context.added_delta(item))),
+        }
+    }
+
+    // the patch keeps the kept items in their order and appends the added ones, so the order is
+    // only recorded when that gives another one, either way
+    let left_keys: Vec<String> = left
+        .iter()
+        .filter_map(|item| item_key(item, key_field))
+        .collect();
+    let right_keys: Vec<String> = right
+        .iter()
+        .filter_map(|item| item_key(item, key_field))
+        .collect();
+    let is_patched_order = |from: &[String],
+                            to: &[String],
+                            from_indices: &HashMap<String, usize>,
+                            to_indices: &HashMap<String, usize>| {
+        from.iter()
+            .filter(|key| to_indices.contains_key(*key))
+            .chain(to.iter().filter(|key| !from_indices.contains_key(*key)))
+            .eq(to.iter())
+    };
+    let order = (!is_patched_order(&left_keys, &right_keys, &left_indices, &right_indices)
+        || !is_patched_order(&right_keys, &left_keys, &right_indices, &left_indices))
+    .then_some(KeyedOrder {
+        left: left_keys,
+        right: right_keys,
+    });
+
+    if changes.is_empty() && new_children_context.is_empty() && order.is_none() {
+        context.set_result(Delta::None).exit();
+    } else {
+        context
+            .set_result(Delta::Keyed {
+                key: key_field.to_string(),
+                changes,
+                order,
+            })
+            .exit();
+    }
+    true
+}
+
+/// Whether the array being diffed is compared as a multiset, see `ArrayOptions::unordered`
+fn is_unordered(context: &DiffContext) -> bool {
+    let Some(array_options) = context.options().arrays.as_ref() else {
+        return false;
+    };
+    if array_options.unordered.unwrap_or(false) {
+        return true;
+    }
+    array_options
+        .unordered_paths
+        .as_ref()
+        .is_some_and(|patterns| {
+            let path = context.path();
+            patterns.iter().any(|pattern| path_matches(pattern, &path))
+        })
+}
+
+/// Diffs two arrays as multisets: each item of the right array consumes an equal item of the
+/// left array, the items left over on either side are the removed and added ones. Removed items
+/// are found by value when patching, so with `omit_removed_values` the changes are given by index
+/// instead, as an array delta removing them and appending the added ones.
+fn process_unordered_arrays_diff<'a>(
+    context: &mut DiffContext<'a>,
+    left: &'a [Value],
+    right: &'a [Value],
+) {
+    // equal items have equal hashes, except that a property filter makes more items equal
+    let has_property_filter = context.options().property_filter.is_some();
+    let key = |item: &Value| {
+        if has_property_filter {
+            0
+        } else {
+            context
+                .subtree_hash(item)
+                .unwrap_or_else(|| value_hash(item))
+        }
+    };
+
+    let mut left_by_key: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (index, item) in left.iter().enumerate() {
+        left_by_key.entry(key(item)).or_default().push(index);
+    }
+
+    let mut added = Vec::new();
+    for (right_index, item) in right.iter().enumerate() {
+        let same_item = left_by_key.get_mut(&key(item)).and_then(|candidates| {
+            let name = right_index.to_string();
+            let position = candidates
+                .iter()
+                .position(|&index| context.child_values_equal(&name, &left[index], item))?;
+            Some(candidates.remove(position))
+        });
+        if same_item.is_none() {
+            added.push(item);
+        }
+    }
+
+    let mut removed_indices: Vec<usize> = left_by_key.into_values().flatten().collect();
+    removed_indices.sort_unstable();
+
+    if removed_indices.is_empty() && added.is_empty() {
+        context.set_result(Delta::None).exit();
+    } else if context.omit_removed_values() {
+        let kept_count = left.len() - removed_indices.len();
+        let removed = removed_indices.into_iter().map(|index| {
+            (
+                ArrayDeltaIndex::RemovedOrMoved(index),
+                context.deleted_delta(&left[index]),
+            )
+        });
+        let added = added.into_iter().enumerate().map(|(offset, item)| {
+            (
+                ArrayDeltaIndex::NewOrModified(kept_count + offset),
+                context.added_delta(item),
+            )
+        });
+        let array_changes = removed.chain(added).collect();
+        context.set_result(Delta::Array(array_changes)).exit();
+    } else {
+        let removed = removed_indices
+            .into_iter()
+            .map(|index| context.delta_value(&left[index]))
+            .collect();
+        let added = added
+            .into_iter()
+            .map(|item| context.delta_value(item))
+            .collect();
+        context
+            .set_result(Delta::Unordered { removed, added })
+            .exit();
+    }
+}
+
 pub fn post_process_arrays_diff<'a>(
     context: &mut DiffContext<'a>,
     children_context: &mut Vec<(String, DiffContext<'a>)>,
 ) -> Result<(), JsonDiffPatchError> {
-    // Handle post-processing of array diff results
-    // This would collect results from child contexts and merge them
     if children_context.is_empty() {
         return Ok(());
     }
 
-    // Collect results from children and merge them into the array delta
-    let mut array_changes = Vec::new();
+    // Merge the nested changes of the children into the structural changes (additions, removals
+    // and moves) found while processing the array. Children are named after their new index, or
+    // after the key of their item in keyed arrays.
+    let mut array_changes = match context.pop_result() {
+        Some(Delta::Array(array_changes)) => array_changes,
+        Some(Delta::Keyed {
+            key,
+            mut changes,
+            order,
+        }) => {
+            for (item_key, child_context) in children_context {
+                match child_context.pop_result() {
+                    Some(Delta::None) | None => {}
+                    Some(child_result) => changes.push((item_key.clone(), child_result)),
+                }
+            }
+            if !changes.is_empty() || order.is_some() {
+                context
+                    .set_result(Delta::Keyed {
+                        key,
+                        changes,
+                        order,
+                    })
+                    .exit();
+            }
+            return Ok(());
+        }
+        _ => Vec::new(),
+    };
 
     for (index_str, child_context) in children_context {
         if let Some(child_result) = child_context.pop_result() {
             if let Delta::None = child_result {
                 continue;
             }
-            let index: usize = index_str.parse().unwrap_or(0);
+            let index = index_str.parse::<usize>().map_err(|_| {
+                JsonDiffPatchError::InternalPatchLogicError(format!(
+                    "invalid array index '{}'",
+                    index_str
+                ))
+            })?;
             array_changes.push((ArrayDeltaIndex::NewOrModified(index), child_result));
         }
     }
@@ -263,6 +675,71 @@ pub fn post_process_arrays_diff<'a>(
     Ok(())
 }
 
+/// Maps indices of a patched array back to indices of the original array.
+///
+/// Array deltas are applied by first removing every item at a `_N` index (deletions and move
+/// sources), then inserting every item at a `N` index (additions and move targets) in increasing
+/// order. Any other item keeps its relative order, which is enough to locate it in the original.
+pub(crate) struct ArrayIndexMap {
+    removed: Vec<usize>,
+    inserted: Vec<usize>,
+    moved: HashMap<usize, usize>,
+}
+
+impl ArrayIndexMap {
+    pub fn new(array_changes: &[(ArrayDeltaIndex, Delta<'_>)]) -> Self {
+        let mut removed = Vec::new();
+        let mut inserted = Vec::new();
+        let mut moved = HashMap::new();
+
+        for (index, delta) in array_changes {
+            match (index, delta) {
+                (ArrayDeltaIndex::RemovedOrMoved(old_index), Delta::Moved { new_index, .. }) => {
+                    removed.push(*old_index);
+                    inserted.push(*new_index);
+                    moved.insert(*new_index, *old_index);
+                }
+                (ArrayDeltaIndex::RemovedOrMoved(old_index), _) => removed.push(*old_index),
+                (ArrayDeltaIndex::NewOrModified(new_index), Delta::Added(_)) => {
+                    inserted.push(*new_index)
+                }
+                (ArrayDeltaIndex::NewOrModified(_), _) => {}
+            }
+        }
+        removed.sort_unstable();
+        inserted.sort_unstable();
+
+        Self {
+            removed,
+            inserted,
+            moved,
+        }
+    }
+
+    /// Returns the original index of the item found at `new_index` once the delta is applied, or
+    /// `None` if that item was added by the delta.
+    pub fn old_index(&self, new_index: usize) -> Option<usize> {
+        if let Some(&old_index) = self.moved.get(&new_index) {
+            return Some(old_index);
+        }
+        if self.inserted.binary_search(&new_index).is_ok() {
+            return None;
+        }
+
+        // position of the item among the items that were neither removed nor inserted
+        let kept_position = new_index - self.inserted.partition_point(|&i| i < new_index);
+        let mut old_index = kept_position;
+        for &removed_index in &self.removed {
+            if removed_index <= old_index {
+                old_index += 1;
+            } else {
+                break;
+            }
+        }
+        Some(old_index)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -366,7 +843,8 @@ mod tests {
         for test_case in test_cases {
             println!("Running test: {}", test_case.name);
 
-            let mut context = PatchContext::new(&test_case.original, test_case.delta);
+            let mut context =
+                PatchContext::new(&test_case.original, test_case.delta, Default::default());
 
             let mut new_children_context = Vec::new();
             let result = {
@@ -435,7 +913,8 @@ mod tests {
         for test_case in edge_cases {
             println!("Running edge case test: {}", test_case.name);
 
-            let mut context = PatchContext::new(&test_case.original, test_case.delta);
+            let mut context =
+                PatchContext::new(&test_case.original, test_case.delta, Default::default());
 
             let mut new_children_context = Vec::new();
             let result = {
@@ -466,4 +945,478 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_arrays_diff_with_object_hash() {
+        let by_id = crate::DiffPatcher::new(Some(crate::Options {
+            object_hash: Some(std::sync::Arc::new(|item: &Value, _index: usize| {
+                item.get("id").map(|id| id.to_string())
+            })),
+            ..Default::default()
+        }));
+        let by_value = crate::DiffPatcher::new(None);
+
+        let left = serde_json::json!([{"id": 1, "v": 1}, {"id": 2, "v": 2}, {"id": 3, "v": 3}]);
+        let right = serde_json::json!([{"id": 1, "v": 1}, {"id": 2, "v": 20}, {"id": 3, "v": 3}]);
+        assert_eq!(
+            serde_json::to_value(by_id.diff(&left, &right).unwrap()).unwrap(),
+            serde_json::json!({"_t": "a", "1": {"v": [2, 20]}})
+        );
+        assert_eq!(
+            serde_json::to_value(by_value.diff(&left, &right).unwrap()).unwrap(),
+            serde_json::json!({"_t": "a", "_1": [{"id": 2, "v": 2}, null, 0], "1": [{"id": 2, "v": 20}]})
+        );
+
+        // items without a hash are still matched by value
+        let left = serde_json::json!([{"id": 1}, {"name": "x"}, 5]);
+        let right = serde_json::json!([{"name": "x"}, {"id": 1}, 5]);
+        let delta = serde_json::to_value(by_id.diff(&left, &right).unwrap()).unwrap();
+        let changes = delta.as_object().unwrap();
+        assert_eq!(changes.len(), 2, "expected a single move, got {}", delta);
+        assert!(changes
+            .values()
+            .any(|change| change.get(2) == Some(&serde_json::json!(3))));
+    }
+
+    #[test]
+    fn test_arrays_diff_keeps_structural_and_nested_changes() {
+        let by_id = crate::DiffPatcher::new(Some(crate::Options {
+            object_hash: Some(std::sync::Arc::new(|item: &Value, _index: usize| {
+                item.get("id").map(|id| id.to_string())
+            })),
+            ..Default::default()
+        }));
+
+        let left = serde_json::json!([{"id": 1, "v": 1}, {"id": 2, "v": 2}, {"id": 3, "v": 3}]);
+        let right = serde_json::json!([{"id": 0}, {"id": 1, "v": 10}, {"id": 3, "v": 30}]);
+        let delta = by_id.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({
+                "_t": "a",
+                "_1": [{"id": 2, "v": 2}, null, 0],
+                "0": [{"id": 0}],
+                "1": {"v": [1, 10]},
+                "2": {"v": [3, 30]}
+            })
+        );
+        assert_eq!(by_id.patch(&left, delta.clone()), Some(right.clone()));
+        assert_eq!(by_id.unpatch(&right, &delta), Some(left.clone()));
+
+        let by_position = crate::DiffPatcher::new(Some(crate::Options {
+            match_by_position: Some(true),
+            ..Default::default()
+        }));
+        let left = serde_json::json!([{"a": 1}, [1], 3]);
+        let right = serde_json::json!([{"a": 2}, [1], 3, 4]);
+        let delta = by_position.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "0": {"a": [1, 2]}, "3": [4]})
+        );
+        assert_eq!(by_position.patch(&left, delta), Some(right));
+    }
+
+    #[test]
+    fn test_arrays_diff_large_arrays() {
+        let left: Value = (0..20_000).map(|i| serde_json::json!({"id": i})).collect();
+        let right: Value = (0..20_000)
+            .filter(|i| i % 50 != 0)
+            .map(|i| serde_json::json!({"id": i}))
+            .chain((1..=100).map(|i| serde_json::json!({"id": -i})))
+            .collect();
+
+        let delta = crate::diff(&left, &right).unwrap();
+        let Delta::Array(changes) = &delta else {
+            panic!("expected an array delta, got {:?}", delta);
+        };
+        assert_eq!(changes.len(), 400 + 100);
+        assert_eq!(crate::patch(&left, delta), Some(right));
+    }
+
+    #[test]
+    fn test_arrays_diff_pairs_similar_items() {
+        let by_similarity = crate::DiffPatcher::new(Some(crate::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                detect_move: Some(true),
+                include_value_on_move: Some(false),
+                similarity_threshold: Some(0.5),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+
+        let left = serde_json::json!([
+            {"level": "info", "message": "started", "pid": 1},
+            {"level": "info", "message": "listening", "port": 80},
+            {"level": "warn", "message": "slow", "ms": 300}
+        ]);
+        let right = serde_json::json!([
+            {"level": "info", "message": "started", "pid": 1},
+            {"level": "info", "message": "listening", "port": 8080},
+            {"other": true},
+            {"level": "warn", "message": "slow", "ms": 300}
+        ]);
+
+        let delta = by_similarity.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "1": {"port": [80, 8080]}, "2": [{"other": true}]})
+        );
+        assert_eq!(
+            by_similarity.patch(&left, delta.clone()),
+            Some(right.clone())
+        );
+        assert_eq!(by_similarity.unpatch(&right, &delta), Some(left.clone()));
+
+        // items below the threshold are still replaced
+        assert_eq!(
+            serde_json::to_value(crate::diff(&left, &right).unwrap()).unwrap(),
+            serde_json::json!({
+                "_t": "a",
+                "_1": [{"level": "info", "message": "listening", "port": 80}, null, 0],
+                "1": [{"level": "info", "message": "listening", "port": 8080}],
+                "2": [{"other": true}]
+            })
+        );
+    }
+
+    #[test]
+    fn test_arrays_diff_moved_and_modified_items() {
+        let by_id = crate::DiffPatcher::new(Some(crate::Options {
+            object_hash: Some(std::sync::Arc::new(|item: &Value, _index: usize| {
+                item.get("id").map(|id| id.to_string())
+            })),
+            ..Default::default()
+        }));
+        let left = serde_json::json!([{"id": 1, "v": 1}, {"id": 2, "v": 2}, {"id": 3, "v": 3}]);
+        let right = serde_json::json!([{"id": 3, "v": 30}, {"id": 1, "v": 1}, {"id": 2, "v": 2}]);
+        let delta = by_id.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "_2": ["", 0, 3], "0": {"v": [3, 30]}})
+        );
+        assert_eq!(by_id.patch(&left, delta.clone()), Some(right.clone()));
+        assert_eq!(by_id.unpatch(&right, &delta), Some(left));
+
+        let by_similarity = crate::DiffPatcher::new(Some(crate::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                detect_move: Some(true),
+                include_value_on_move: Some(false),
+                similarity_threshold: Some(0.5),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let left = serde_json::json!([
+            {"name": "a", "x": 1, "y": 1},
+            {"name": "b", "x": 2, "y": 2},
+            {"name": "c", "x": 3, "y": 3, "z": 3}
+        ]);
+        let right = serde_json::json!([
+            {"name": "c", "x": 3, "y": 3, "z": 30},
+            {"name": "a", "x": 1, "y": 1},
+            {"name": "b", "x": 2, "y": 2}
+        ]);
+        let delta = by_similarity.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "_2": ["", 0, 3], "0": {"z": [3, 30]}})
+        );
+        assert_eq!(
+            by_similarity.patch(&left, delta.clone()),
+            Some(right.clone())
+        );
+        assert_eq!(by_similarity.unpatch(&right, &delta), Some(left));
+    }
+
+    #[test]
+    fn test_arrays_diff_match_by_position() {
+        let by_position = crate::DiffPatcher::new(Some(crate::Options {
+            match_by_position: Some(true),
+            ..Default::default()
+        }));
+
+        let left = serde_json::json!([1, 2, 3]);
+        let right = serde_json::json!([2, 3, 4]);
+        let delta = by_position.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "0": [1, 2], "1": [2, 3], "2": [3, 4]})
+        );
+        assert_eq!(by_position.patch(&left, delta), Some(right));
+
+        let left = serde_json::json!([[1, 2], [3, 4]]);
+        let right = serde_json::json!([[1, 2], [3, 4], [5, 6], [7, 8]]);
+        let delta = by_position.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "2": [[5, 6]], "3": [[7, 8]]})
+        );
+        assert_eq!(by_position.patch(&left, delta), Some(right.clone()));
+
+        let delta = by_position.diff(&right, &left).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "_2": [[5, 6], null, 0], "_3": [[7, 8], null, 0]})
+        );
+        assert_eq!(by_position.patch(&right, delta), Some(left));
+    }
+
+    #[test]
+    fn test_arrays_diff_unordered() {
+        let unordered = crate::DiffPatcher::new(Some(crate::types::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                unordered: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+
+        let left = serde_json::json!(["a", "b", {"c": 1}, "a", "d"]);
+        let reordered = serde_json::json!([{"c": 1}, "d", "a", "b", "a"]);
+        assert!(unordered.diff(&left, &reordered).is_none());
+
+        let right = serde_json::json!(["e", {"c": 1}, "a", "b", "e"]);
+        let delta = unordered.diff(&left, &right).unwrap();
+        let serialized = serde_json::to_value(&delta).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({"_t": "u", "-": ["a", "d"], "+": ["e", "e"]})
+        );
+
+        let patched = unordered.patch(&left, delta.clone()).unwrap();
+        assert!(unordered.diff(&patched, &right).is_none());
+        let unpatched = unordered.unpatch(&right, &delta).unwrap();
+        assert!(unordered.diff(&unpatched, &left).is_none());
+        assert_eq!(
+            crate::patch(&left, Delta::from_value(serialized).unwrap()),
+            Some(patched)
+        );
+
+        let err = unordered
+            .try_patch(&serde_json::json!(["a"]), delta)
+            .unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::UnorderedItemNotFound { .. }),
+            "{:?}",
+            err
+        );
+
+        // omitted removed items cannot be found by value, so they are removed by index
+        let compact = crate::DiffPatcher::new(Some(crate::types::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                unordered: Some(true),
+                ..Default::default()
+            }),
+            omit_removed_values: Some(true),
+            ..Default::default()
+        }));
+        let delta = compact.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({"_t": "a", "_3": [0, 0, 4], "_4": [0, 0, 4], "3": ["e"], "4": ["e"]})
+        );
+        let patched = compact.patch(&left, delta.clone()).unwrap();
+        assert!(unordered.diff(&patched, &right).is_none());
+        assert!(matches!(
+            compact.try_unpatch(&right, &delta),
+            Err(JsonDiffPatchError::Reverse(
+                crate::errors::JsonDiffPatchReverseError::MissingRemovedValue
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_arrays_diff_unordered_paths() {
+        let diffpatcher = crate::DiffPatcher::new(Some(crate::types::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                unordered_paths: Some(vec!["/users/*/tags".to_string()]),
+                ..Default::default()
+            }),
+            object_hash: Some(std::sync::Arc::new(|item: &Value, _index: usize| {
+                item.get("id").map(|id| id.to_string())
+            })),
+            ..Default::default()
+        }));
+
+        let left = serde_json::json!({
+            "users": [{"id": 1, "tags": ["x", "y"], "ids": [1, 2]}],
+            "tags": ["x", "y"]
+        });
+        let right = serde_json::json!({
+            "users": [{"id": 1, "tags": ["z", "y", "x"], "ids": [2, 1]}],
+            "tags": ["y", "x"]
+        });
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::json!({
+                "users": {"_t": "a", "0": {
+                    "tags": {"_t": "u", "-": [], "+": ["z"]},
+                    "ids": {"_t": "a", "_1": ["", 0, 3]}
+                }},
+                "tags": {"_t": "a", "_1": ["", 0, 3]}
+            })
+        );
+        assert_eq!(
+            diffpatcher.patch(&left, delta),
+            Some(serde_json::json!({
+                "users": [{"id": 1, "tags": ["x", "y", "z"], "ids": [2, 1]}],
+                "tags": ["y", "x"]
+            }))
+        );
+    }
+
+    #[test]
+    fn test_arrays_diff_keyed() {
+        let keyed = crate::DiffPatcher::new(Some(crate::types::Options {
+            arrays: Some(crate::types::ArrayOptions {
+                keyed_paths: Some([("/spec/containers".to_string(), "name".to_string())].into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let containers =
+            |containers: Value| serde_json::json!({"spec": {"containers": containers}});
+
+        let left = containers(serde_json::json!([
+            {"name": "web", "image": "web:1"},
+            {"name": "db", "image": "postgres"},
+            {"name": "cache", "image": "redis"}
+        ]));
+        let right = containers(serde_json::json!([
+            {"name": "db", "image": "postgres"},
+            {"name": "web", "image": "web:2"},
+            {"name": "log", "image": "fluentd"}
+        ]));
+        let delta = keyed.diff(&left, &right).unwrap();
+        let serialized = serde_json::to_value(&delta).unwrap();
+        assert_eq!(
+            serialized,
+            serde_json::json!({"spec": {"containers": {
+                "_t": "k",
+                "_key": "name",
+                "_order": [["web", "db", "cache"], ["db", "web", "log"]],
+                "cache": [{"name": "cache", "image": "redis"}, null, 0],
+                "log": [{"name": "log", "image": "fluentd"}],
+                "web": {"image": ["web:1", "web:2"]}
+            }}})
+        );
+        assert_eq!(keyed.patch(&left, delta.clone()), Some(right.clone()));
+        assert_eq!(keyed.unpatch(&right, &delta), Some(left.clone()));
+        let reordered = containers(serde_json::json!([
+            {"name": "cache", "image": "redis"},
+            {"name": "db", "image": "postgres"},
+            {"name": "web", "image": "web:1"}
+        ]));
+        assert_eq!(
+            crate::patch(&reordered, Delta::from_value(serialized).unwrap()),
+            Some(right.clone())
+        );
+
+        let err = keyed
+            .try_patch(&containers(serde_json::json!([{"name": "db"}])), delta)
+            .unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::KeyedItemNotFound { .. }),
+            "{:?}",
+            err
+        );
+
+        // the patched items end up in the order of the right array
+        let cases = [
+            (
+                serde_json::json!([{"name": 1}, {"name": 2}]),
+                serde_json::json!([{"name": 2}, {"name": 1}]),
+                Some(serde_json::json!([["1", "2"], ["2", "1"]])),
+            ),
+            (
+                serde_json::json!([{"name": 1}, {"name": 2}]),
+                serde_json::json!([{"name": 0}, {"name": 1}, {"name": 2}]),
+                Some(serde_json::json!([["1", "2"], ["0", "1", "2"]])),
+            ),
+            // removing an item from the middle puts it back at the end when unpatching
+            (
+                serde_json::json!([{"name": 1}, {"name": 2}, {"name": 3}]),
+                serde_json::json!([{"name": 1}, {"name": 3, "v": 1}]),
+                Some(serde_json::json!([["1", "2", "3"], ["1", "3"]])),
+            ),
+            // items appended and removed at the end need no order either way
+            (
+                serde_json::json!([{"name": 1}, {"name": 2}, {"name": 3}]),
+                serde_json::json!([{"name": 1}, {"name": 2, "v": 1}, {"name": 0}]),
+                None,
+            ),
+        ];
+        for (left, right, order) in cases {
+            let (left, right) = (containers(left), containers(right));
+            let delta = keyed.diff(&left, &right).unwrap();
+            assert_eq!(
+                serde_json::to_value(&delta).unwrap()["spec"]["containers"].get("_order"),
+                order.as_ref()
+            );
+            assert_eq!(keyed.patch(&left, delta.clone()), Some(right.clone()));
+            assert_eq!(keyed.unpatch(&right, &delta), Some(left));
+        }
+
+        // repeated keys, including keys of different types with the same text, and keys that
+        // are fields of the serialized delta fall back to comparing positions
+        for unkeyed in [
+            serde_json::json!([{"name": "a"}, {"name": "a", "x": 1}]),
+            serde_json::json!([{"name": 1}, {"name": "1", "x": 1}]),
+            serde_json::json!([{"name": "_t"}]),
+        ] {
+            assert_eq!(
+                serde_json::to_value(keyed.diff(&left, &containers(unkeyed)).unwrap()).unwrap()
+                    ["spec"]["containers"]["_t"],
+                "a"
+            );
+        }
+    }
+
+    #[test]
+    fn test_item_keys_follow_matches() {
+        let object_hash: crate::types::ObjectHash =
+            // only some of the items have a hash, equal to unhashed items on the other side
+            std::sync::Arc::new(|item, index| {
+                item.get("id").filter(|_| index < 2).map(Value::to_string)
+            });
+        let property_filter: crate::types::PropertyFilter =
+            std::sync::Arc::new(|key, _| key != "updated");
+        let options = [
+            crate::types::Options::default(),
+            crate::types::Options {
+                object_hash: Some(object_hash),
+                ..Default::default()
+            },
+            crate::types::Options {
+                property_filter: Some(property_filter),
+                ..Default::default()
+            },
+        ];
+        let left =
+            serde_json::json!([{"id": 1, "updated": 1}, {"id": 2, "v": 1}, {"v": 1}, [1], 1]);
+        let right =
+            serde_json::json!([{"v": 1}, {"id": 1, "updated": 2}, {"id": 2, "v": 1}, [1], 1]);
+        let (left_items, right_items) = (left.as_array().unwrap(), right.as_array().unwrap());
+
+        for options in options {
+            let context = DiffContext::new(&left, &right, std::sync::Arc::new(options));
+            let matcher = ItemMatcher::new(&context, left_items, right_items);
+            for (left_index, left_item) in left_items.iter().enumerate() {
+                for (right_index, right_item) in right_items.iter().enumerate() {
+                    if matcher.matches(left_index, right_index) {
+                        assert_eq!(
+                            matcher.left_key(left_index),
+                            matcher.right_key(right_index),
+                            "{} and {}",
+                            left_item,
+                            right_item
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
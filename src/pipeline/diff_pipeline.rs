@@ -19,41 +19,65 @@ impl<'a> Pipeline<DiffContext<'a>, Delta<'a>> for DiffPipeline {
         context: &mut DiffContext<'a>,
         new_children_context: &mut Vec<(String, DiffContext<'a>)>,
     ) -> Result<(), JsonDiffPatchError> {
-        if context.left == context.right {
-            // same value
+        let (left, right) = match (context.left, context.right) {
+            (Some(left), Some(right)) => (left, right),
+            (Some(left), None) => {
+                // removed property
+                context.set_result(context.deleted_delta(left)).exit();
+                return Ok(());
+            }
+            (None, Some(right)) => {
+                // new added property
+                context.set_result(context.added_delta(right)).exit();
+                return Ok(());
+            }
+            (None, None) => {
+                context.set_result(Delta::None).exit();
+                return Ok(());
+            }
+        };
+
+        if (!left.is_object() || context.has_subtree_hashes()) && context.values_equal(left, right)
+        {
+            // without subtree hashes, objects are compared property by property by their
+            // children instead, so that nested objects are not compared again at every level
             context.set_result(Delta::None).exit();
-        } else if context.right.is_null() {
-            // new added value
-            context.set_result(Delta::deleted_ref(context.left)).exit();
-        } else if context.left.is_boolean()
-            || context.left.is_number()
-            || (std::mem::discriminant(context.left) != std::mem::discriminant(context.right))
+        } else if left.is_null()
+            || left.is_boolean()
+            || left.is_number()
+            || (std::mem::discriminant(left) != std::mem::discriminant(right))
         {
-            // trivial value / different types
+            // trivial value / different types, an explicit null is a value like any other
             context
-                .set_result(Delta::modified_ref(context.left, context.right))
+                .set_result(context.modified_delta(left, right))
                 .exit();
         } else {
             // now left's type must equals to right's type
-            match &context.left {
-                Value::Object(object) => {
-                    let left = object;
-                    let right = context.right.as_object().expect("right is an object");
-                    // TODO: add property filter
+            match left {
+                Value::Object(left) => {
+                    let right = right.as_object().expect("right is an object");
+                    let property_filter = context.options().property_filter.clone();
+                    let is_diffed = |key: &str| {
+                        property_filter
+                            .as_ref()
+                            .is_none_or(|property_filter| property_filter(key, context))
+                    };
 
                     // process keys from left (with potential deletions)
-                    for (key, value) in left {
+                    for (key, value) in left.iter().filter(|(key, _)| is_diffed(key)) {
                         new_children_context.push((
                             key.to_string(),
-                            DiffContext::new(value, right.get(key).unwrap_or(&Value::Null)),
+                            context.new_property_child(Some(value), right.get(key)),
                         ));
                     }
 
                     // process keys from right (with potential additions)
-                    for (key, value) in right {
+                    for (key, value) in right.iter().filter(|(key, _)| is_diffed(key)) {
                         if !left.contains_key(key) {
-                            new_children_context
-                                .push((key.to_string(), DiffContext::new(&Value::Null, value)));
+                            new_children_context.push((
+                                key.to_string(),
+                                context.new_property_child(None, Some(value)),
+                            ));
                         }
                     }
 
@@ -68,24 +92,21 @@ impl<'a> Pipeline<DiffContext<'a>, Delta<'a>> for DiffPipeline {
                     process_arrays_diff(
                         context,
                         array,
-                        context.right.as_array().expect("right is an array"),
+                        right.as_array().expect("right is an array"),
                         new_children_context,
                     )?;
                 }
-                Value::Null => {
-                    context.set_result(Delta::added_ref(context.right)).exit();
-                }
-                Value::String(string) => {
-                    process_text_diff(
-                        context,
-                        string,
-                        context.right.as_str().expect("right is a string"),
-                    )?;
+                Value::String(_) => {
+                    process_text_diff(context, left, right)?;
                 }
-                Value::Bool(_) => unreachable!(),
-                Value::Number(_) => unreachable!(),
+                Value::Null | Value::Bool(_) | Value::Number(_) => unreachable!(),
             }
         }
+
+        // children are named after their object key or array index
+        for (name, child_context) in new_children_context.iter_mut() {
+            child_context.push_path_segment(name);
+        }
         Ok(())
     }
 
@@ -94,8 +115,8 @@ impl<'a> Pipeline<DiffContext<'a>, Delta<'a>> for DiffPipeline {
         context: &mut DiffContext<'a>,
         children_context: &mut Vec<(String, DiffContext<'a>)>,
     ) -> Result<(), JsonDiffPatchError> {
-        match &context.left {
-            Value::Object(_object) => {
+        match context.left {
+            Some(Value::Object(_object)) => {
                 let mut result = HashMap::new();
 
                 for (key, child) in children_context {
@@ -109,15 +130,117 @@ impl<'a> Pipeline<DiffContext<'a>, Delta<'a>> for DiffPipeline {
 
                 context.set_result(Delta::Object(result)).exit();
             }
-            Value::Array(_array) => {
+            Some(Value::Array(_array)) => {
                 post_process_arrays_diff(context, children_context)?;
                 return Ok(());
             }
-            Value::Bool(_) => {}
-            Value::Null => {}
-            Value::Number(_) => {}
-            Value::String(_) => {}
+            _ => {}
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::path::path_matches;
+    use crate::types::Options;
+    use crate::DiffPatcher;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_property_filter_skips_volatile_keys() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            property_filter: Some(Arc::new(|key, _context| {
+                !matches!(key, "updatedAt" | "etag" | "_rev")
+            })),
+            ..Default::default()
+        }));
+
+        let left = json!({
+            "etag": "1",
+            "a": {"updatedAt": 1, "x": 1, "b": {"_rev": "1", "y": 1}},
+            "list": [{"id": 1, "_rev": "a"}, {"id": 2, "_rev": "b"}, {"id": 3, "v": 1}]
+        });
+        let volatile_only = json!({
+            "etag": "2",
+            "a": {"updatedAt": 2, "x": 1, "b": {"_rev": "2", "y": 1}},
+            "list": [{"id": 1, "_rev": "c"}, {"id": 2, "_rev": "d"}, {"id": 3, "v": 1, "updatedAt": 5}]
+        });
+        assert!(diffpatcher.diff(&left, &volatile_only).is_none());
+
+        let right = json!({
+            "a": {"updatedAt": 2, "x": 2, "b": {"_rev": "2", "y": 2}},
+            "list": [{"id": 2, "_rev": "d"}, {"id": 1, "_rev": "c"}, {"id": 3, "v": 1}]
+        });
+        assert_eq!(
+            serde_json::to_value(diffpatcher.diff(&left, &right).unwrap()).unwrap(),
+            json!({
+                "a": {"x": [1, 2], "b": {"y": [1, 2]}},
+                "list": {"_t": "a", "_1": ["", 0, 3]}
+            })
+        );
+    }
+
+    #[test]
+    fn test_property_filter_sees_nested_paths() {
+        // only the `seen` counters of the metadata of documents are volatile
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            property_filter: Some(Arc::new(|key, context| {
+                key != "seen" || !path_matches("/docs/*/meta", &context.path())
+            })),
+            ..Default::default()
+        }));
+
+        let left = json!({
+            "docs": [{"meta": {"seen": 1, "v": 1}}, {"meta": {"seen": 1, "v": 1}}],
+            "seen": 1
+        });
+        let right = json!({
+            "docs": [{"meta": {"seen": 2, "v": 1}}, {"meta": {"seen": 2, "v": 2}}],
+            "seen": 2
+        });
+        assert_eq!(
+            serde_json::to_value(diffpatcher.diff(&left, &right).unwrap()).unwrap(),
+            json!({
+                "docs": {
+                    "_t": "a",
+                    "_1": [{"meta": {"seen": 1, "v": 1}}, null, 0],
+                    "1": [{"meta": {"seen": 2, "v": 2}}]
+                },
+                "seen": [1, 2]
+            })
+        );
+    }
+
+    #[test]
+    fn test_explicit_null_is_not_a_missing_key() {
+        let cases = [
+            (json!({"a": 1}), json!({"a": null}), json!({"a": [1, null]})),
+            (json!({"a": null}), json!({"a": 1}), json!({"a": [null, 1]})),
+            (json!({"a": null}), json!({}), json!({"a": [null, null, 0]})),
+            (json!({}), json!({"a": null}), json!({"a": [null]})),
+            (json!(null), json!(5), json!([null, 5])),
+            (
+                json!([{"a": null}]),
+                json!([{}]),
+                json!({"_t": "a", "_0": [{"a": null}, null, 0], "0": [{}]}),
+            ),
+        ];
+
+        for (left, right, expected) in cases {
+            let delta = crate::diff(&left, &right).unwrap();
+            assert_eq!(
+                serde_json::to_value(&delta).unwrap(),
+                expected,
+                "{} -> {}",
+                left,
+                right
+            );
+            assert_eq!(crate::patch(&left, delta.clone()), Some(right.clone()));
+            assert_eq!(crate::unpatch(&right, &delta), Some(left));
+        }
+
+        assert!(crate::diff(&json!({"a": null}), &json!({"a": null})).is_none());
+    }
+}
@@ -1,12 +1,14 @@
 use crate::context::patch::DeltaIndicator;
 use crate::context::{FilterContext, PatchContext};
 use crate::errors::JsonDiffPatchError;
+use crate::pipeline::arrays::{item_key, ArrayIndexMap};
 use crate::pipeline::texts::DMP;
 use crate::processor::Pipeline;
-use crate::types::{ArrayDeltaIndex, Delta};
+use crate::types::{ArrayDeltaIndex, Delta, KeyedOrder};
 use diff_match_patch_rs::Efficient;
-use serde_json::Value;
+use serde_json::{Map, Value};
 use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
 
 pub struct PatchPipeline;
 
@@ -23,8 +25,8 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
         let res = match context.take_delta() {
             Delta::Object(object_delta) => {
                 for (key, value) in object_delta {
-                    let child =
-                        PatchContext::new(context.left.get(&key).unwrap_or(&Value::Null), value);
+                    let child = context
+                        .new_property_child(context.left.and_then(|left| left.get(&key)), value);
                     new_children_context.push((key.to_string(), child));
                 }
                 None
@@ -32,7 +34,7 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
             Delta::Array(array_delta) => {
                 let mut container = vec![];
                 let result = handle_array(
-                    context.left.as_array().ok_or_else(|| {
+                    context.left.and_then(Value::as_array).ok_or_else(|| {
                         JsonDiffPatchError::InvalidPatchToTarget {
                             patch: "array".to_string(),
                         }
@@ -42,7 +44,7 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
                 )?;
                 // handle new children
                 for (name, value, delta) in container {
-                    let child_context = PatchContext::new(value, delta);
+                    let child_context = context.new_child(value, delta);
                     new_children_context.push((name, child_context));
                 }
 
@@ -63,7 +65,7 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
                 ));
             }
             Delta::TextDiff(text_diff) => {
-                let Value::String(left_txt) = context.left else {
+                let Some(Value::String(left_txt)) = context.left else {
                     return Err(JsonDiffPatchError::InvalidPatchToTarget {
                         patch: "text diff".to_string(),
                     });
@@ -86,6 +88,53 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
                     }
                 }
             }
+            Delta::Unordered { removed, added } => {
+                let mut new_array = context
+                    .left
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| JsonDiffPatchError::InvalidPatchToTarget {
+                        patch: "unordered array".to_string(),
+                    })?
+                    .clone();
+                // items are found by value, wherever they are
+                for item in removed {
+                    let position = new_array
+                        .iter()
+                        .position(|value| value == item.as_ref())
+                        .ok_or_else(|| JsonDiffPatchError::UnorderedItemNotFound {
+                            item: item.clone().into_owned(),
+                        })?;
+                    new_array.remove(position);
+                }
+                new_array.extend(added.into_iter().map(Cow::into_owned));
+
+                Some(Cow::Owned(Value::Array(new_array)))
+            }
+            Delta::Keyed {
+                key,
+                changes,
+                order,
+            } => {
+                let mut container = vec![];
+                let result = handle_keyed_array(
+                    context.left.and_then(Value::as_array).ok_or_else(|| {
+                        JsonDiffPatchError::InvalidPatchToTarget {
+                            patch: "keyed array".to_string(),
+                        }
+                    })?,
+                    &key,
+                    changes,
+                    order.as_ref(),
+                    &mut container,
+                )?;
+                // children are named after the index of their item in the patched array
+                for (name, value, delta) in container {
+                    let child_context = context.new_child(value, delta);
+                    new_children_context.push((name, child_context));
+                }
+
+                Some(Cow::Owned(result))
+            }
             Delta::None => None,
         };
         if let Some(res) = res {
@@ -100,7 +149,7 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
         children_context: &mut Vec<(String, PatchContext<'a>)>,
     ) -> Result<(), JsonDiffPatchError> {
         match context.peek_delta() {
-            DeltaIndicator::Array => {
+            DeltaIndicator::Array | DeltaIndicator::Keyed => {
                 // Collect results from children and apply them to the array
 
                 let current_result = context
@@ -115,9 +164,11 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
                 for (index_str, child_context) in children_context {
                     if let Some(child_result) = child_context.pop_result() {
                         if let Ok(index) = index_str.parse::<usize>() {
-                            if index < array_mut.len() {
-                                array_mut[index] = child_result.into_owned();
-                            }
+                            let length = array_mut.len();
+                            let item = array_mut.get_mut(index).ok_or(
+                                JsonDiffPatchError::IndexOutOfBoundsModify { index, length },
+                            )?;
+                            *item = child_result.into_owned();
                         }
                     }
                 }
@@ -125,16 +176,24 @@ impl<'a> Pipeline<PatchContext<'a>, Cow<'a, Value>> for PatchPipeline {
                 // context.set_result(Value::Array(array)).exit();
             }
             DeltaIndicator::Object => {
+                let invalid_target = || JsonDiffPatchError::InvalidPatchToTarget {
+                    patch: "object".to_string(),
+                };
+                let left = context.left.ok_or_else(invalid_target)?;
                 let result = if children_context.is_empty() {
-                    Cow::Borrowed(context.left)
+                    Cow::Borrowed(left)
                 } else {
-                    let mut new_object = context
-                        .left
-                        .as_object()
-                        .ok_or_else(|| JsonDiffPatchError::InvalidPatchToTarget {
-                            patch: "object".to_string(),
-                        })?
-                        .clone();
+                    let left_object = left.as_object().ok_or_else(invalid_target)?;
+                    // only copy the untouched properties, the patched ones are replaced below
+                    let patched_keys: HashSet<&str> = children_context
+                        .iter()
+                        .map(|(key, _)| key.as_str())
+                        .collect();
+                    let mut new_object: Map<String, Value> = left_object
+                        .iter()
+                        .filter(|(key, _)| !patched_keys.contains(key.as_str()))
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect();
 
                     // Collect results from children and apply them to the object
                     for (key, child_context) in children_context {
@@ -167,6 +226,7 @@ pub(crate) fn handle_array<'a>(
 
     // Sort the array delta by index
     array_delta.sort_by_key(|(index, _)| index.clone());
+    let index_map = ArrayIndexMap::new(&array_delta);
 
     // Remove items, in reverse order to avoid index shifting issues
     for (index, delta) in array_delta.into_iter().rev() {
@@ -203,11 +263,22 @@ pub(crate) fn handle_array<'a>(
                     Delta::Added(value) => {
                         to_insert.push((new_index, value));
                     }
-                    Delta::Modified(..) => {
-                        // Modified item - will be handled by child contexts
-                        let value = &left[new_index];
+                    Delta::Modified(..)
+                    | Delta::Object(_)
+                    | Delta::Array(_)
+                    | Delta::TextDiff(_)
+                    | Delta::Unordered { .. }
+                    | Delta::Keyed { .. } => {
+                        // nested change - will be handled by child contexts, against the item that
+                        // ends up at this index once the structural changes are applied
+                        let value = index_map
+                            .old_index(new_index)
+                            .and_then(|old_index| left.get(old_index))
+                            .ok_or(JsonDiffPatchError::IndexOutOfBoundsModify {
+                                index: new_index,
+                                length: left.len(),
+                            })?;
                         return_container.push((new_index.to_string(), value, delta));
-                        // re-construct the delta
                     }
                     _ => {
                         return Err(JsonDiffPatchError::InvalidPatch(
@@ -234,3 +305,173 @@ pub(crate) fn handle_array<'a>(
 
     Ok(Value::Array(new_array))
 }
+
+/// Applies the structural changes (additions, deletions and reordering) of a keyed array delta,
+/// and returns the nested changes in `return_container` along with the index of their item in the
+/// result
+pub(crate) fn handle_keyed_array<'a>(
+    left: &'a [Value],
+    key_field: &str,
+    changes: Vec<(String, Delta<'a>)>,
+    order: Option<&KeyedOrder>,
+    return_container: &mut Vec<(String, &'a Value, Delta<'a>)>,
+) -> Result<Value, JsonDiffPatchError> {
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for (index, item) in left.iter().enumerate() {
+        if let Some(key) = item_key(item, key_field) {
+            positions.entry(key).or_insert(index);
+        }
+    }
+    let not_found = |key: &str| JsonDiffPatchError::KeyedItemNotFound {
+        key_field: key_field.to_string(),
+        key: key.to_string(),
+    };
+
+    let mut removed = vec![false; left.len()];
+    let mut nested_changes = Vec::new();
+    let mut to_append = Vec::new();
+    for (key, delta) in changes {
+        match delta {
+            Delta::Added(value) => {
+                if positions.contains_key(&key) {
+                    return Err(JsonDiffPatchError::KeyedItemExists {
+                        key_field: key_field.to_string(),
+                        key,
+                    });
+                }
+                to_append.push(value);
+            }
+            Delta::Deleted(_) => {
+                let index = *positions.get(&key).ok_or_else(|| not_found(&key))?;
+                removed[index] = true;
+            }
+            Delta::Moved { .. } => {
+                return Err(JsonDiffPatchError::InvalidPatch(
+                    "items of keyed arrays cannot be moved".to_string(),
+                ));
+            }
+            Delta::None => {}
+            delta => {
+                let index = *positions.get(&key).ok_or_else(|| not_found(&key))?;
+                nested_changes.push((key, index, delta));
+            }
+        }
+    }
+
+    // the kept items stay in their order, the added ones go after them
+    let mut new_indices = vec![None; left.len()];
+    let mut new_array = Vec::with_capacity(left.len() + to_append.len());
+    for (index, item) in left.iter().enumerate() {
+        if !removed[index] {
+            new_indices[index] = Some(new_array.len());
+            new_array.push(item.clone());
+        }
+    }
+    new_array.extend(to_append.into_iter().map(Cow::into_owned));
+
+    // then they are put in the recorded order, if any
+    let mut ordered_indices: Vec<usize> = (0..new_array.len()).collect();
+    if let Some(order) = order {
+        let order_positions: HashMap<&str, usize> = order
+            .right
+            .iter()
+            .enumerate()
+            .map(|(position, key)| (key.as_str(), position))
+            .collect();
+        let mismatch = || {
+            JsonDiffPatchError::InvalidPatch(format!(
+                "the order of the keyed array does not match its items by {}",
+                key_field
+            ))
+        };
+        if order_positions.len() != new_array.len() {
+            return Err(mismatch());
+        }
+        let mut ordered = vec![None; new_array.len()];
+        for (item, position) in new_array.into_iter().zip(&mut ordered_indices) {
+            *position = item_key(&item, key_field)
+                .and_then(|key| order_positions.get(key.as_str()).copied())
+                .filter(|&position| ordered[position].is_none())
+                .ok_or_else(mismatch)?;
+            ordered[*position] = Some(item);
+        }
+        new_array = ordered.into_iter().flatten().collect();
+    }
+
+    for (key, index, delta) in nested_changes {
+        let new_index = new_indices[index].ok_or_else(|| not_found(&key))?;
+        return_container.push((ordered_indices[new_index].to_string(), &left[index], delta));
+    }
+
+    Ok(Value::Array(new_array))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::types::{ArrayOptions, Delta, Options};
+    use crate::{diff, patch, unpatch, DiffPatcher};
+    use serde_json::json;
+
+    #[test]
+    fn test_patch_nested_deltas_in_arrays() {
+        let left = json!([{"a": 1}, [1, 2], "some long text to diff", 4]);
+        let right = json!([{"a": 2}, [1, 3], "some long text to patch", 4]);
+        let delta = diff(&left, &right).unwrap();
+        assert_eq!(patch(&left, delta.clone()), Some(right.clone()));
+        assert_eq!(unpatch(&right, &delta), Some(left.clone()));
+
+        // nested changes are applied to the item found at their new index
+        let delta = Delta::from_value(json!({
+            "_t": "a",
+            "_0": [1, 0, 0],
+            "0": {"b": [1, 2]},
+            "2": ["new"]
+        }))
+        .unwrap();
+        assert_eq!(
+            patch(&json!([1, {"a": 1, "b": 1}, {"c": 1}]), delta),
+            Some(json!([{"a": 1, "b": 2}, {"c": 1}, "new"]))
+        );
+    }
+
+    #[test]
+    fn test_patch_unordered_arrays_in_arrays() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            match_by_position: Some(true),
+            arrays: Some(ArrayOptions {
+                unordered_paths: Some(vec!["/*".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let left = json!([[1, 2], [3]]);
+        let right = json!([[2, 1, 5], [3]]);
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+        assert!(matches!(&delta, Delta::Array(changes)
+            if matches!(changes[..], [(_, Delta::Unordered { .. })])));
+        // the items of unordered arrays are only compared as multisets
+        let patched = diffpatcher.patch(&left, delta.clone()).unwrap();
+        assert!(diffpatcher.diff(&patched, &right).is_none());
+        let unpatched = diffpatcher.unpatch(&right, &delta).unwrap();
+        assert!(diffpatcher.diff(&unpatched, &left).is_none());
+    }
+
+    #[test]
+    fn test_patch_keyed_arrays_in_arrays() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            match_by_position: Some(true),
+            arrays: Some(ArrayOptions {
+                keyed_paths: Some([("/*".to_string(), "id".to_string())].into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }));
+        let left = json!([[{"id": 1, "v": 1}, {"id": 2}], [{"id": 3}]]);
+        let right = json!([[{"id": 2}, {"id": 1, "v": 2}], [{"id": 3}]]);
+        let delta = diffpatcher.diff(&left, &right).unwrap();
+        assert!(matches!(&delta, Delta::Array(changes)
+            if matches!(changes[..], [(_, Delta::Keyed { .. })])));
+        assert_eq!(diffpatcher.patch(&left, delta.clone()), Some(right.clone()));
+        assert_eq!(diffpatcher.unpatch(&right, &delta), Some(left));
+    }
+}
@@ -1,225 +1,191 @@
-use crate::context::patch::DeltaIndicator;
-use crate::context::{FilterContext, PatchContext};
+use crate::context::reverse::ReverseContext;
+use crate::context::FilterContext;
 use crate::errors::JsonDiffPatchError;
-use crate::pipeline::texts::DMP;
 use crate::processor::Pipeline;
-use crate::types::{ArrayDeltaIndex, Delta};
-use diff_match_patch_rs::Efficient;
-use serde_json::{Map, Value};
-use std::borrow::Cow;
+use crate::types::{reverse_array_changes, ArrayDeltaIndex, Delta, KeyedOrder};
+use std::collections::HashMap;
 
 pub struct ReversePipeline;
 
-impl<'a> Pipeline<PatchContext<'a>, Value> for ReversePipeline {
+impl<'a> Pipeline<ReverseContext<'a>, Delta<'a>> for ReversePipeline {
     fn filter_name(&self) -> &str {
         "reverse-pipeline"
     }
 
     fn process(
         &self,
-        context: &mut PatchContext<'a>,
-        new_children_context: &mut Vec<(String, PatchContext<'a>)>,
+        context: &mut ReverseContext<'a>,
+        new_children_context: &mut Vec<(String, ReverseContext<'a>)>,
     ) -> Result<(), JsonDiffPatchError> {
-        match context.take_delta() {
-            Delta::Object(_object_delta) => {
-                todo!()
+        match std::mem::replace(&mut context.delta, Delta::None) {
+            Delta::Object(object_delta) => {
+                for (key, delta) in object_delta {
+                    new_children_context.push((key, context.new_child(delta)));
+                }
+                // filled in by the children during post-processing
+                context.set_result(Delta::Object(HashMap::new())).exit();
             }
             Delta::Array(array_delta) => {
-                let mut container = vec![];
-                let result = handle_array(
-                    context.left.as_array().ok_or_else(|| {
-                        JsonDiffPatchError::InvalidPatchToTarget {
-                            patch: "array".to_string(),
-                        }
-                    })?,
-                    &array_delta,
-                    &mut container,
-                )?;
-                // handle new children
-                for (name, value, delta) in container {
-                    let child_context = PatchContext::new(value, delta);
-                    new_children_context.push((name, child_context));
+                // structural changes are reversed here, nested changes are reversed by the
+                // children, under the index of their item in the original array
+                let (reversed_changes, nested_changes) = reverse_array_changes(array_delta)?;
+                for (index, delta) in nested_changes {
+                    new_children_context.push((index.to_string(), context.new_child(delta)));
                 }
-
-                context.set_result(Cow::Owned(result)).exit();
-            }
-            Delta::Added(new_value) => {
-                context.set_result(new_value);
+                context.set_result(Delta::Array(reversed_changes)).exit();
             }
-            Delta::Deleted(_old_value) => {
-                // dont apply this value to the result to keep it as deleted
-            }
-            Delta::Modified(_from, to) => {
-                context.set_result(to);
-            }
-            Delta::Moved {
-                new_index: _,
-                moved_value: _,
+            Delta::Keyed {
+                key,
+                changes,
+                order,
             } => {
-                return Err(JsonDiffPatchError::InternalPatchLogicError(
-                    "Should be handled by array directly, as move does not make sense for non-array container".to_string(),
-                ));
-            }
-            Delta::TextDiff(text_diff) => {
-                let Value::String(left_txt) = context.left else {
-                    return Err(JsonDiffPatchError::InvalidPatchToTarget {
-                        patch: "text diff".to_string(),
-                    });
-                };
-                let left_txt = left_txt.as_str();
-                // context.set_result(text_diff.clone()).exit();
-                match DMP.patch_from_text::<Efficient>(text_diff.as_str()) {
-                    Ok(patches) => {
-                        let (new_txt, ops) = DMP.patch_apply(&patches, left_txt)?;
-                        ops.iter().for_each(|op| {
-                            if !op {
-                                log::error!("some text-diff patch applied failed");
-                            }
-                        });
-
-                        context
-                            .set_result(Cow::Owned(Value::String(new_txt)))
-                            .exit();
-                    }
-                    Err(e) => {
-                        return Err(JsonDiffPatchError::ApplyTextDiffFailed(e));
-                    }
+                // items are found by key either way, so every change is reversed on its own
+                for (item_key, delta) in changes {
+                    new_children_context.push((item_key, context.new_child(delta)));
                 }
+                context
+                    .set_result(Delta::Keyed {
+                        key,
+                        changes: Vec::new(),
+                        order: order.map(KeyedOrder::reverse),
+                    })
+                    .exit();
+            }
+            delta => {
+                context.set_result(delta.build_reverse()?).exit();
             }
-            Delta::None => {}
         }
         Ok(())
     }
 
     fn post_process(
         &self,
-        context: &mut PatchContext<'a>,
-        children_context: &mut Vec<(String, PatchContext<'a>)>,
+        context: &mut ReverseContext<'a>,
+        children_context: &mut Vec<(String, ReverseContext<'a>)>,
     ) -> Result<(), JsonDiffPatchError> {
-        match context.peek_delta() {
-            DeltaIndicator::Array => {
-                // Collect results from children and apply them to the array
-
-                let current_result = context
-                    .get_result_mut()
-                    .expect("should be set during the main patch process");
-                let array_mut = current_result.to_mut().as_array_mut().ok_or_else(|| {
-                    JsonDiffPatchError::InvalidPatchToTarget {
-                        patch: "array".to_string(),
-                    }
-                })?;
-
-                for (index_str, child_context) in children_context {
+        match context.get_result_mut() {
+            Some(Delta::Object(reversed_changes)) => {
+                for (key, child_context) in children_context {
                     if let Some(child_result) = child_context.pop_result() {
-                        if let Ok(index) = index_str.parse::<usize>() {
-                            if index < array_mut.len() {
-                                array_mut[index] = child_result.into_owned();
-                            }
-                        }
+                        reversed_changes.insert(key.clone(), child_result);
                     }
                 }
-                // array is modified in-place.
-                // context.set_result(Value::Array(array)).exit();
             }
-            DeltaIndicator::Object => {
-                let mut reversed_changes = Map::new();
-
-                // Collect results from children and apply them to the object
-                for (key, child_context) in children_context
-                    .iter_mut()
-                    .filter_map(|(key, context)| context.pop_result().map(|result| (key, result)))
-                {
-                    reversed_changes.insert(key.clone(), child_context.into_owned());
-                }
-                context
-                    .set_result(Cow::Owned(Value::Object(reversed_changes)))
-                    .exit();
-            }
-            _ => {}
-        };
-        Ok(())
-    }
-}
-
-pub(crate) fn handle_array<'a>(
-    left: &'a [Value],
-    array_delta: &Vec<(ArrayDeltaIndex, Delta<'a>)>,
-    return_container: &mut Vec<(String, &'a Value, Delta<'a>)>,
-) -> Result<Value, JsonDiffPatchError> {
-    let mut new_array = left.to_vec();
-
-    let mut to_insert: Vec<(usize, Value)> = Vec::new();
-
-    // create a vector of references to the array delta, but with the indices sorted
-    let mut sorted_array_delta = array_delta
-        .iter()
-        .map(|(index, delta)| (index, delta))
-        .collect::<Vec<_>>();
-
-    // Remove items, in reverse order to avoid index shifting issues
-    sorted_array_delta.sort_by_key(|(index, _)| *index);
-
-    for (index, delta) in sorted_array_delta.iter().rev() {
-        match index {
-            ArrayDeltaIndex::RemovedOrMoved(removed_index) => {
-                // Check if it's a removal or move
-                if *removed_index >= new_array.len() {
-                    return Err(JsonDiffPatchError::IndexOutOfBoundsRemove {
-                        index: *removed_index,
-                        length: new_array.len(),
-                    });
-                }
-
-                // Check if this was a move operation
-                let removed_value = new_array.remove(*removed_index);
-                match delta {
-                    Delta::Deleted(_) => {
-                        // to_remove.push((*removed_index, None));
-                    }
-                    Delta::Moved { new_index, .. } => {
-                        // We'll handle the reinsertion later, as we want to insert in increasing order
-                        to_insert.push((*new_index, removed_value));
-                    }
-                    _ => {
-                        return Err(JsonDiffPatchError::InvalidPatch(
-                            "only removal or move can be applied at original array indices"
-                                .to_string(),
-                        ));
+            Some(Delta::Keyed { changes, .. }) => {
+                for (item_key, child_context) in children_context {
+                    if let Some(child_result) = child_context.pop_result() {
+                        changes.push((item_key.clone(), child_result));
                     }
                 }
             }
-            ArrayDeltaIndex::NewOrModified(new_index) => {
-                match delta {
-                    Delta::Added(value) => {
-                        to_insert.push((*new_index, (*value.as_ref()).clone()));
-                    }
-                    Delta::Modified(_from, _to) => {
-                        // Modified item - will be handled by child contexts
-                        let value = &left[*new_index];
-                        return_container.push((new_index.to_string(), value, (*delta).clone()));
-                    }
-                    _ => {
-                        return Err(JsonDiffPatchError::InvalidPatch(
-                            "only addition or modification can be applied at new array indices"
-                                .to_string(),
-                        ));
+            Some(Delta::Array(reversed_changes)) => {
+                for (index_str, child_context) in children_context {
+                    if let Some(child_result) = child_context.pop_result() {
+                        let index = index_str.parse::<usize>().map_err(|_| {
+                            JsonDiffPatchError::InternalPatchLogicError(format!(
+                                "invalid array index '{}'",
+                                index_str
+                            ))
+                        })?;
+                        reversed_changes
+                            .push((ArrayDeltaIndex::NewOrModified(index), child_result));
                     }
                 }
             }
+            _ => {}
         }
+        Ok(())
     }
+}
 
-    // Insert items, sorted by index
-    to_insert.sort_by_key(|(index, _)| *index);
-    for (index, value) in to_insert {
-        if index > new_array.len() {
-            return Err(JsonDiffPatchError::IndexOutOfBoundsInsert {
-                index,
-                length: new_array.len(),
-            });
+#[cfg(test)]
+mod tests {
+    use crate::types::{ArrayDeltaIndex, Delta};
+    use crate::{diff, patch, reverse, unpatch};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_unpatch_round_trip() {
+        let long_text = "The quick brown fox jumps over the lazy dog, again and again and again.";
+        let cases: Vec<(&str, Value, Value)> = vec![
+            (
+                "object changes",
+                json!({"name": "John", "age": 30, "city": "New York", "pets": 2}),
+                json!({"name": "John", "age": 31, "city": "Boston", "job": "dev"}),
+            ),
+            (
+                "nested objects",
+                json!({"a": {"b": {"c": 1, "d": [1, 2]}}, "e": true}),
+                json!({"a": {"b": {"c": 2, "d": [1, 2]}, "x": "y"}, "e": false}),
+            ),
+            (
+                "array moves",
+                json!([1, 2, 3, 4, 5, 6, 7]),
+                json!([1, 2, 4, 5, 3, 6, 7]),
+            ),
+            (
+                "array additions and removals",
+                json!(["a", "b", "c", "d", "e"]),
+                json!(["x", "b", "d", "y", "e", "z"]),
+            ),
+            (
+                "arrays from and to empty",
+                json!({"a": [], "b": [1, 2]}),
+                json!({"a": [1, 2], "b": []}),
+            ),
+            (
+                "array reordering",
+                json!(["a", "b", "c", "d", "e", "f"]),
+                json!(["f", "c", "a", "e", "b", "d"]),
+            ),
+            (
+                "text diff",
+                json!({"text": long_text}),
+                json!({"text": long_text.replace("lazy", "sleepy").replace("again.", "once more.")}),
+            ),
+        ];
+
+        for (name, left, right) in cases {
+            let delta = diff(&left, &right).unwrap();
+            let reversed = reverse(&delta).unwrap();
+            assert_eq!(
+                patch(&right, reversed).as_ref(),
+                Some(&left),
+                "case '{}' failed to patch with the reversed delta",
+                name
+            );
+            assert_eq!(
+                unpatch(&right, &delta).as_ref(),
+                Some(&left),
+                "case '{}' failed to unpatch",
+                name
+            );
         }
-        new_array.insert(index, value);
     }
 
-    Ok(Value::Array(new_array))
+    #[test]
+    fn test_reverse_array_delta_with_nested_changes() {
+        let a = json!("a");
+        let x = json!("x");
+        let old = json!(1);
+        let new = json!(2);
+        // ["a", "b", 1] -> ["x", "b", 2, "a"]
+        let delta = Delta::Array(vec![
+            (ArrayDeltaIndex::RemovedOrMoved(0), Delta::moved_ref(&a, 3)),
+            (ArrayDeltaIndex::NewOrModified(0), Delta::added_ref(&x)),
+            (
+                ArrayDeltaIndex::NewOrModified(2),
+                Delta::modified_ref(&old, &new),
+            ),
+        ]);
+        let left = json!(["a", "b", 1]);
+        let right = json!(["x", "b", 2, "a"]);
+
+        let reversed = delta.build_reverse().unwrap();
+        assert_eq!(
+            serde_json::to_value(&reversed).unwrap(),
+            json!({"_t": "a", "_3": ["a", 0, 3], "_0": ["x", null, 0], "2": [2, 1]})
+        );
+        assert_eq!(patch(&right, reversed), Some(left));
+    }
 }
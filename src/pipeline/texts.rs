@@ -3,6 +3,8 @@ use crate::errors::JsonDiffPatchError;
 use crate::types::Delta;
 use diff_match_patch_rs::{DiffMatchPatch, Efficient, PatchInput};
 use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
 
 const DEFAULT_MIN_LENGTH: usize = 60;
 
@@ -10,11 +12,21 @@ pub struct PatchPipeline;
 
 pub(crate) static DMP: Lazy<DiffMatchPatch> = Lazy::new(DiffMatchPatch::new);
 
+static PATCH_HEADER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^@@ -(\d+(?:,\d+)?) \+(\d+(?:,\d+)?) @@$").expect("valid patch header regex")
+});
+
 pub fn process_text_diff<'a>(
     context: &mut DiffContext<'a>,
-    left: &str,
-    right: &str,
+    left: &'a Value,
+    right: &'a Value,
 ) -> Result<(), JsonDiffPatchError> {
+    let (Value::String(left_text), Value::String(right_text)) = (left, right) else {
+        return Err(JsonDiffPatchError::InternalPatchLogicError(
+            "text diff expects two strings".to_string(),
+        ));
+    };
+
     // Get minimum length from options or use default
     let min_length = context
         .options()
@@ -24,15 +36,15 @@ pub fn process_text_diff<'a>(
         .unwrap_or(DEFAULT_MIN_LENGTH);
 
     // If strings are too short, use regular string replace
-    if left.len() < min_length || right.len() < min_length {
+    if left_text.len() < min_length || right_text.len() < min_length {
         context
-            .set_result(Delta::modified_ref(context.left, context.right))
+            .set_result(context.modified_delta(left, right))
             .exit();
         return Ok(());
     }
 
     // Try to use text-diff algorithm
-    let diffs = DMP.diff_main::<Efficient>(left, right)?;
+    let diffs = DMP.diff_main::<Efficient>(left_text, right_text)?;
     // Now, we are going to create a list of `patches` to be applied to the old text to get the new text
     let patches = DMP.patch_make(PatchInput::new_diffs(&diffs))?;
     // in the real world you are going to transmit or store this diff serialized to undiff format to be consumed or used somewhere else
@@ -41,3 +53,34 @@ pub fn process_text_diff<'a>(
     context.set_result(Delta::TextDiff(patch_txt)).exit();
     Ok(())
 }
+
+/// Reverses a text diff (in the unidiff-like patch format of diff-match-patch), so that applying
+/// it to the new text gives back the old one.
+pub fn reverse_text_diff(text_diff: &str) -> String {
+    let mut lines = Vec::new();
+    // keep deletions before insertions within each hunk, as diff-match-patch does
+    let mut deletions = Vec::new();
+    let mut insertions = Vec::new();
+
+    for line in text_diff.split('\n') {
+        if let Some(inserted) = line.strip_prefix('+') {
+            deletions.push(format!("-{}", inserted));
+            continue;
+        }
+        if let Some(deleted) = line.strip_prefix('-') {
+            insertions.push(format!("+{}", deleted));
+            continue;
+        }
+
+        lines.append(&mut deletions);
+        lines.append(&mut insertions);
+        match PATCH_HEADER.captures(line) {
+            Some(header) => lines.push(format!("@@ -{} +{} @@", &header[2], &header[1])),
+            None => lines.push(line.to_string()),
+        }
+    }
+    lines.append(&mut deletions);
+    lines.append(&mut insertions);
+
+    lines.join("\n")
+}
@@ -1,7 +1,7 @@
 use crate::context::FilterContext;
 use crate::errors::JsonDiffPatchError;
 use crate::types::Options;
-use crate::types::OPTIONS;
+use std::sync::Arc;
 
 pub trait Pipeline<C, TResult> {
     fn filter_name(&self) -> &str;
@@ -21,14 +21,19 @@ pub trait Pipeline<C, TResult> {
     }
 }
 
-pub struct Processor {}
+pub struct Processor {
+    options: Arc<Options>,
+}
 
 impl Processor {
     pub fn new(options: Option<Options>) -> Self {
-        OPTIONS
-            .set(options.unwrap_or_default())
-            .expect("options failed to set");
-        Self {}
+        Self {
+            options: Arc::new(options.unwrap_or_default()),
+        }
+    }
+
+    pub fn options(&self) -> &Arc<Options> {
+        &self.options
     }
 
     pub fn process<TContext: FilterContext>(
@@ -36,30 +41,179 @@ impl Processor {
         context: &mut TContext,
         pipeline: &mut impl Pipeline<TContext, TContext::Result>,
     ) -> Result<(), JsonDiffPatchError> {
-        process_pipeline(context, pipeline)
+        process_pipeline(context, pipeline, self.options.max_depth)
     }
 }
 
+/// A context whose children are being processed
+struct Frame<TContext> {
+    name: String,
+    context: TContext,
+    pending_children: std::vec::IntoIter<(String, TContext)>,
+    processed_children: Vec<(String, TContext)>,
+}
+
+/// Processes the context and all its descendants depth-first, calling `post_process` on a context
+/// once all its children are done.
+///
+/// An explicit stack is used rather than recursion, so that deeply nested JSON cannot overflow the
+/// call stack. Dropping, cloning, serializing, reversing (`Delta::build_reverse`) and converting
+/// (`Delta::into_owned`) a `Delta` are still recursive, as are cloning and dropping a `Value` in
+/// serde_json, so these may still overflow the stack for very deeply nested values.
 fn process_pipeline<TContext: FilterContext>(
     context: &mut TContext,
     pipeline: &mut impl Pipeline<TContext, TContext::Result>,
+    max_depth: Option<usize>,
 ) -> Result<(), JsonDiffPatchError> {
-    let mut new_children_context = vec![];
+    let mut root_children = vec![];
+    pipeline.process(context, &mut root_children)?;
+
+    let mut root_pending_children = root_children.into_iter();
+    let mut root_processed_children = vec![];
+    // the chain of contexts from a child of the root down to the one being processed
+    let mut stack: Vec<Frame<TContext>> = vec![];
+
+    loop {
+        let next_child = match stack.last_mut() {
+            Some(frame) => frame.pending_children.next(),
+            None => root_pending_children.next(),
+        };
 
-    pipeline.process(context, &mut new_children_context)?;
+        match next_child {
+            Some((name, mut child)) => {
+                if let Some(max_depth) = max_depth {
+                    if stack.len() + 1 > max_depth {
+                        return Err(JsonDiffPatchError::MaxDepthExceeded { max_depth });
+                    }
+                }
 
-    if new_children_context.is_empty() {
-        // continue to process the next queue
-        // continue;
+                let mut new_children_context = vec![];
+                pipeline.process(&mut child, &mut new_children_context)?;
+                stack.push(Frame {
+                    name,
+                    context: child,
+                    pending_children: new_children_context.into_iter(),
+                    processed_children: vec![],
+                });
+            }
+            None => {
+                // all children of the current context are done
+                let Some(mut frame) = stack.pop() else {
+                    break;
+                };
+                pipeline.post_process(&mut frame.context, &mut frame.processed_children)?;
+
+                let parent_processed_children = match stack.last_mut() {
+                    Some(parent) => &mut parent.processed_children,
+                    None => &mut root_processed_children,
+                };
+                parent_processed_children.push((frame.name, frame.context));
+            }
+        }
     }
 
-    for (_key, child) in &mut new_children_context {
-        // recursively process the child context
-        // it's better to use a stack here, as, if the json is too deep, it will cause a stack overflow
-        process_pipeline(child, pipeline)?;
+    pipeline.post_process(context, &mut root_processed_children)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::JsonDiffPatchError;
+    use crate::types::Options;
+    use crate::DiffPatcher;
+    use serde_json::{json, Map, Value};
+
+    fn nested_object(depth: usize, leaf: Value) -> Value {
+        let mut value = leaf;
+        for _ in 0..depth {
+            value = Value::Object(Map::from_iter([("a".to_string(), value)]));
+        }
+        value
     }
 
-    pipeline.post_process(context, &mut new_children_context)?;
+    fn nested_array(depth: usize, leaf: Value) -> Value {
+        let mut value = leaf;
+        for _ in 0..depth {
+            value = Value::Array(vec![json!(0), value]);
+        }
+        value
+    }
 
-    Ok(())
+    /// serde_json drops values recursively, so take deeply nested ones apart iteratively
+    fn drop_nested(value: Value) {
+        let mut pending = vec![value];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Object(object) => pending.extend(object.into_iter().map(|(_, v)| v)),
+                Value::Array(array) => pending.extend(array),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn test_deeply_nested_values() {
+        let diffpatcher = DiffPatcher::new(None);
+        let left = nested_object(10_000, json!(1));
+        let right = nested_object(10_000, json!(2));
+
+        let delta = diffpatcher.try_diff(&left, &right).unwrap().unwrap();
+        let patched = diffpatcher.try_patch(&left, delta).unwrap().unwrap();
+        assert!(diffpatcher.try_diff(&patched, &right).unwrap().is_none());
+
+        drop_nested(left);
+        drop_nested(right);
+        drop_nested(patched);
+    }
+
+    #[test]
+    fn test_deeply_nested_arrays() {
+        // patching clones the added values, which serde_json does recursively: run with the stack
+        // size of a main thread rather than the smaller one of test threads
+        let test = std::thread::Builder::new().stack_size(8 << 20).spawn(|| {
+            let diffpatcher = DiffPatcher::new(None);
+            let left = nested_array(5_000, json!(1));
+            let right = nested_array(5_000, json!(2));
+
+            let delta = diffpatcher.try_diff(&left, &right).unwrap().unwrap();
+            let patched = diffpatcher
+                .try_patch(&left, delta.clone())
+                .unwrap()
+                .unwrap();
+            assert!(diffpatcher.try_diff(&patched, &right).unwrap().is_none());
+            let unpatched = diffpatcher.try_unpatch(&right, &delta).unwrap().unwrap();
+            assert!(diffpatcher.try_diff(&unpatched, &left).unwrap().is_none());
+
+            drop(delta);
+            drop_nested(left);
+            drop_nested(right);
+            drop_nested(patched);
+            drop_nested(unpatched);
+        });
+        test.unwrap().join().unwrap();
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let diffpatcher = DiffPatcher::new(Some(Options {
+            max_depth: Some(2),
+            ..Default::default()
+        }));
+
+        let left = json!({"a": {"b": 1}});
+        let right = json!({"a": {"b": 2}});
+        let delta = diffpatcher.try_diff(&left, &right).unwrap().unwrap();
+        assert_eq!(diffpatcher.try_patch(&left, delta).unwrap(), Some(right));
+
+        let err = diffpatcher
+            .try_diff(
+                &json!({"a": {"b": {"c": 1}}}),
+                &json!({"a": {"b": {"c": 2}}}),
+            )
+            .unwrap_err();
+        assert!(
+            matches!(err, JsonDiffPatchError::MaxDepthExceeded { max_depth: 2 }),
+            "{:?}",
+            err
+        );
+    }
 }
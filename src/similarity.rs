@@ -0,0 +1,83 @@
+//! Structural similarity of JSON values, used to pair up array items that have no identity.
+
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The leaves of a value (scalars, empty objects and empty arrays), keyed by their JSON pointer
+/// path within the value
+#[derive(Debug)]
+pub struct Leaves<'a> {
+    leaves: HashMap<String, &'a Value>,
+}
+
+impl<'a> Leaves<'a> {
+    pub fn new(value: &'a Value) -> Self {
+        let mut leaves = HashMap::new();
+        let mut pending = vec![(String::new(), value)];
+        while let Some((path, value)) = pending.pop() {
+            match value {
+                Value::Object(object) if !object.is_empty() => {
+                    pending.extend(object.iter().map(|(key, item)| {
+                        let key = key.replace('~', "~0").replace('/', "~1");
+                        (format!("{}/{}", path, key), item)
+                    }));
+                }
+                Value::Array(array) if !array.is_empty() => {
+                    pending.extend(
+                        array
+                            .iter()
+                            .enumerate()
+                            .map(|(index, item)| (format!("{}/{}", path, index), item)),
+                    );
+                }
+                _ => {
+                    leaves.insert(path, value);
+                }
+            }
+        }
+        Self { leaves }
+    }
+
+    /// The fraction of equal leaves among the leaves of both values (their Jaccard index), from
+    /// 0 for values with nothing in common to 1 for equal values
+    pub fn similarity(&self, other: &Leaves) -> f64 {
+        let equal = self
+            .leaves
+            .iter()
+            .filter(|(path, value)| other.leaves.get(*path) == Some(*value))
+            .count();
+        let total = self.leaves.len() + other.leaves.len() - equal;
+        if total == 0 {
+            return 1.0;
+        }
+        equal as f64 / total as f64
+    }
+}
+
+/// The fraction of equal leaf paths of two values, see [`Leaves::similarity`]
+pub fn similarity(left: &Value, right: &Value) -> f64 {
+    Leaves::new(left).similarity(&Leaves::new(right))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_similarity() {
+        let record = json!({"level": "info", "message": "started", "tags": ["a", "b"]});
+        assert_eq!(similarity(&record, &record), 1.0);
+        assert_eq!(
+            similarity(
+                &record,
+                &json!({"level": "warn", "message": "started", "tags": ["a", "b"]})
+            ),
+            3.0 / 5.0
+        );
+        assert_eq!(similarity(&record, &json!({"other": 1})), 0.0);
+        assert_eq!(similarity(&json!(1), &json!(1)), 1.0);
+        assert_eq!(similarity(&json!(1), &json!("1")), 0.0);
+        assert_eq!(similarity(&json!({"a/b": 1}), &json!({"a": {"b": 1}})), 0.0);
+    }
+}
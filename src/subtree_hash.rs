@@ -0,0 +1,206 @@
+//! Hashes of every subtree of the diffed documents, computed once before diffing so that unequal
+//! subtrees can be told apart without comparing them.
+
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+
+/// The hash of each object and array of some documents, keyed by the address of the value.
+///
+/// Equal values always have equal hashes, so values with different hashes are known to be
+/// different. Values with equal hashes still have to be compared, as hashes may collide.
+#[derive(Debug, Default)]
+pub(crate) struct SubtreeHashes {
+    hashes: HashMap<usize, u64, BuildHasherDefault<AddressHasher>>,
+}
+
+impl SubtreeHashes {
+    pub fn new(documents: &[&Value]) -> Self {
+        let mut subtree_hashes = Self::default();
+        let container_count = documents.iter().copied().map(container_count).sum();
+        subtree_hashes.hashes.reserve(container_count);
+        for document in documents {
+            subtree_hashes.add(document);
+        }
+        subtree_hashes
+    }
+
+    /// The hash of an object or array of the documents, `None` for any other value
+    pub fn get(&self, value: &Value) -> Option<u64> {
+        self.hashes.get(&address(value)).copied()
+    }
+
+    fn add(&mut self, document: &Value) {
+        hash_subtrees(document, |value, hash| {
+            // scalars are compared as fast as their hashes
+            if value.is_object() || value.is_array() {
+                self.hashes.insert(address(value), hash);
+            }
+        });
+    }
+}
+
+/// The hash of a single value, as it would be found in [`SubtreeHashes`]
+pub(crate) fn value_hash(value: &Value) -> u64 {
+    hash_subtrees(value, |_, _| {})
+}
+
+/// Hashes every value of a document, children before their parent, and returns the hash of the
+/// document
+fn hash_subtrees<'a>(document: &'a Value, mut on_hashed: impl FnMut(&'a Value, u64)) -> u64 {
+    // an explicit stack is used as documents may be deeply nested; the hashes of the children of
+    // the values being hashed are kept in order on `child_hashes`
+    let mut pending = vec![(document, false)];
+    let mut child_hashes = Vec::new();
+    while let Some((value, children_hashed)) = pending.pop() {
+        match value {
+            Value::Array(array) if !children_hashed => {
+                pending.push((value, true));
+                pending.extend(array.iter().rev().map(|item| (item, false)));
+            }
+            Value::Object(object) if !children_hashed => {
+                pending.push((value, true));
+                pending.extend(object.values().rev().map(|item| (item, false)));
+            }
+            _ => {
+                let child_count = match value {
+                    Value::Array(array) => array.len(),
+                    Value::Object(object) => object.len(),
+                    _ => 0,
+                };
+                let children_start = child_hashes.len() - child_count;
+                let hash = hash_value(value, &child_hashes[children_start..]);
+                child_hashes.truncate(children_start);
+                child_hashes.push(hash);
+                on_hashed(value, hash);
+            }
+        }
+    }
+    child_hashes.pop().expect("the document was hashed")
+}
+
+/// The hash of a value, given the hashes of its items or property values
+fn hash_value(value: &Value, child_hashes: &[u64]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    std::mem::discriminant(value).hash(&mut hasher);
+    match value {
+        Value::Null => {}
+        Value::Bool(boolean) => boolean.hash(&mut hasher),
+        Value::Number(number) => number.hash(&mut hasher),
+        Value::String(string) => string.hash(&mut hasher),
+        Value::Array(_) => child_hashes.hash(&mut hasher),
+        Value::Object(object) => {
+            object.len().hash(&mut hasher);
+            // properties are combined regardless of their order, as is object equality
+            let properties =
+                object
+                    .keys()
+                    .zip(child_hashes)
+                    .fold(0u64, |properties, (key, item_hash)| {
+                        let mut property_hasher = DefaultHasher::new();
+                        key.hash(&mut property_hasher);
+                        item_hash.hash(&mut property_hasher);
+                        properties.wrapping_add(property_hasher.finish())
+                    });
+            properties.hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn address(value: &Value) -> usize {
+    value as *const Value as usize
+}
+
+/// The number of objects and arrays in a document, including itself
+fn container_count(document: &Value) -> usize {
+    let mut count = 0;
+    let mut pending = vec![document];
+    while let Some(value) = pending.pop() {
+        match value {
+            Value::Array(array) => pending.extend(array),
+            Value::Object(object) => pending.extend(object.values()),
+            _ => continue,
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Hashes the address of a value, which is already unique, by spreading its bits
+#[derive(Debug, Default)]
+struct AddressHasher(u64);
+
+impl Hasher for AddressHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.write_u64(self.0 ^ u64::from(byte));
+        }
+    }
+
+    fn write_u64(&mut self, address: u64) {
+        // the low bits of the product depend on the low bits of the address, which are the same
+        // for aligned values, hence the rotation
+        self.0 = address.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(32);
+    }
+
+    fn write_usize(&mut self, address: usize) {
+        self.write_u64(address as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiffPatcher, Options};
+    use serde_json::json;
+
+    #[test]
+    fn test_subtree_hashes() {
+        let left = json!({"a": [1, {"b": null}], "c": "d", "e": 1.0});
+        let right = json!({"e": 1.0, "c": "d", "a": [1, {"b": null}], "f": [{"b": null}, 1]});
+        let hashes = SubtreeHashes::new(&[&left, &right]);
+
+        assert_eq!(hashes.get(&left["a"]), hashes.get(&right["a"]));
+        assert_eq!(hashes.get(&left["a"][1]), hashes.get(&right["f"][0]));
+        assert_ne!(hashes.get(&left["a"]), hashes.get(&right["f"]));
+        assert_ne!(hashes.get(&left), hashes.get(&right));
+        assert_ne!(value_hash(&left["a"][0]), value_hash(&left["e"]));
+        assert_eq!(value_hash(&left["a"]), hashes.get(&left["a"]).unwrap());
+        // scalars are compared directly
+        assert_eq!(hashes.get(&left["c"]), None);
+        assert_eq!(hashes.get(&json!(["not in the documents"])), None);
+    }
+
+    #[test]
+    fn test_diff_with_subtree_hashes() {
+        let hashing = DiffPatcher::new(Some(Options {
+            hash_subtrees: Some(true),
+            ..Default::default()
+        }));
+
+        let left = json!({
+            "config": {"servers": [{"host": "a", "port": 1}, {"host": "b", "port": 2}], "debug": false},
+            "list": [[1, 2], [3, 4], {"x": 1}, 5],
+            "text": "unchanged"
+        });
+        let right = json!({
+            "config": {"servers": [{"host": "b", "port": 2}, {"host": "a", "port": 1}], "debug": true},
+            "list": [[3, 4], [1, 2], {"x": 1}, 6],
+            "text": "unchanged"
+        });
+
+        let delta = hashing.diff(&left, &right).unwrap();
+        assert_eq!(
+            serde_json::to_value(&delta).unwrap(),
+            serde_json::to_value(crate::diff(&left, &right).unwrap()).unwrap()
+        );
+        assert_eq!(hashing.patch(&left, delta), Some(right.clone()));
+        assert!(hashing.diff(&right, &right.clone()).is_none());
+    }
+}
@@ -1,14 +1,22 @@
-use crate::errors::JsonDiffPatchReverseError;
-use once_cell::sync::OnceCell;
+use crate::array_matchers::ArrayDiffAlgorithm;
+use crate::context::DiffContext;
+use crate::errors::{JsonDiffPatchDeserializeError, JsonDiffPatchReverseError};
+use crate::pipeline::arrays::ArrayIndexMap;
+use crate::pipeline::texts::reverse_text_diff;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
 // const MIDDLE_NO_VALUE: u32 = 0;
 const MIDDLE_NO_VALUE: Value = Value::Null;
+/// Written in place of removed values omitted from a delta, as the JS library does. Unlike there,
+/// such deltas are told apart by their magic number, so that an actual `0` is never omitted.
+const OMITTED_VALUE: u8 = 0;
 
 #[derive(Serialize_repr, Deserialize_repr)]
 #[repr(u8)]
@@ -16,6 +24,10 @@ pub enum MagicNumber {
     Deleted = 0,
     UndefinedDiff = 2,
     ArrayMoved = 3,
+    /// a deletion whose value is omitted, `[0, 0, 4]`
+    DeletedOmitted = 4,
+    /// a modification whose old value is omitted, `[0, new_value, 5]`
+    ModifiedOmitted = 5,
 }
 
 impl From<MagicNumber> for Value {
@@ -24,47 +36,64 @@ impl From<MagicNumber> for Value {
     }
 }
 
+/// Computes an identity for an array item (given with its index), used to match items of the two
+/// arrays even when their content changed. Returning `None` falls back to matching by equality.
+pub type ObjectHash = Arc<dyn Fn(&Value, usize) -> Option<String> + Send + Sync>;
+
+/// Decides whether an object property (given by its name, along with the context of the object
+/// being diffed) should be diffed at all. Properties for which it returns `false` are ignored.
+pub type PropertyFilter = Arc<dyn Fn(&str, &DiffContext) -> bool + Send + Sync>;
+
 #[derive(Clone)]
 pub struct Options {
-    // pub object_hash: Option<Box<dyn Fn(&Value, Option<usize>) -> Option<String> + Send + Sync>>,
+    pub object_hash: Option<ObjectHash>,
     pub match_by_position: Option<bool>,
     pub arrays: Option<ArrayOptions>,
     pub text_diff: Option<TextDiffOptions>,
-    // pub property_filter: Option<Box<dyn Fn(&str, &DiffContext) -> bool + Send + Sync + 'static>>,
+    pub property_filter: Option<PropertyFilter>,
+    /// Copies the values stored in the delta while diffing, rather than borrowing them from the
+    /// diffed documents. This only saves copying them in [`Delta::into_owned`]: the delta is
+    /// still typed as borrowing them, so use
+    /// [`DiffPatcher::diff_owned`](crate::DiffPatcher::diff_owned) to get a `Delta<'static>`.
     pub clone_diff_values: Option<bool>,
     pub omit_removed_values: Option<bool>,
+    /// Maximum nesting depth to process, deeper values fail with `MaxDepthExceeded`
+    pub max_depth: Option<usize>,
+    /// Hash every subtree of the diffed documents beforehand, so that unequal subtrees are told
+    /// apart without comparing them. Ignored when a `property_filter` is set.
+    pub hash_subtrees: Option<bool>,
 }
 
 impl fmt::Debug for Options {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Options")
-            // .field(
-            //     "object_hash",
-            //     &if self.object_hash.is_some() {
-            //         "Some(Fn)"
-            //     } else {
-            //         "None"
-            //     },
-            // )
+            .field(
+                "object_hash",
+                &if self.object_hash.is_some() {
+                    "Some(Fn)"
+                } else {
+                    "None"
+                },
+            )
             .field("match_by_position", &self.match_by_position)
             .field("arrays", &self.arrays)
             .field("text_diff", &self.text_diff)
-            // .field(
-            //     "property_filter",
-            //     &if self.property_filter.is_some() {
-            //         "Some(Fn)"
-            //     } else {
-            //         "None"
-            //     },
-            // )
+            .field(
+                "property_filter",
+                &if self.property_filter.is_some() {
+                    "Some(Fn)"
+                } else {
+                    "None"
+                },
+            )
             .field("clone_diff_values", &self.clone_diff_values)
             .field("omit_removed_values", &self.omit_removed_values)
+            .field("max_depth", &self.max_depth)
+            .field("hash_subtrees", &self.hash_subtrees)
             .finish()
     }
 }
 
-pub(crate) static OPTIONS: OnceCell<Options> = OnceCell::new();
-
 #[derive(Debug, Clone)]
 pub enum ArrayDeltaIndex {
     NewOrModified(usize),  // index are in-place (previous or new index)
@@ -87,6 +116,18 @@ impl Serialize for ArrayDeltaIndex {
     }
 }
 
+impl FromStr for ArrayDeltaIndex {
+    type Err = std::num::ParseIntError;
+
+    /// Parses an array delta key, `"_3"` being an old index and `"3"` a new one.
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        match key.strip_prefix('_') {
+            Some(index) => Ok(ArrayDeltaIndex::RemovedOrMoved(index.parse()?)),
+            None => Ok(ArrayDeltaIndex::NewOrModified(key.parse()?)),
+        }
+    }
+}
+
 impl PartialEq for ArrayDeltaIndex {
     fn eq(&self, other: &Self) -> bool {
         self.partial_cmp(other).unwrap_or(std::cmp::Ordering::Equal) == std::cmp::Ordering::Equal
@@ -116,11 +157,17 @@ impl Ord for ArrayDeltaIndex {
     }
 }
 
+/// The changes between two JSON values.
+///
+/// Unlike diffing and patching, dropping, cloning, serializing, reversing and converting a delta
+/// are recursive, so deltas of very deeply nested values may overflow the stack there.
 #[derive(Debug, Clone)]
 pub enum Delta<'a> {
     Added(Cow<'a, Value>),
-    Modified(Cow<'a, Value>, Cow<'a, Value>),
-    Deleted(Cow<'a, Value>),
+    /// the old value is `None` when omitted with `omit_removed_values`
+    Modified(Option<Cow<'a, Value>>, Cow<'a, Value>),
+    /// the deleted value is `None` when omitted with `omit_removed_values`
+    Deleted(Option<Cow<'a, Value>>),
     Object(HashMap<String, Delta<'a>>),
     Array(Vec<(ArrayDeltaIndex, Delta<'a>)>),
     Moved {
@@ -128,20 +175,55 @@ pub enum Delta<'a> {
         new_index: usize,
     },
     TextDiff(String),
+    /// changes to an array compared as a multiset (see `ArrayOptions::unordered`): one occurrence
+    /// of each removed item is removed, and the added items are appended
+    Unordered {
+        removed: Vec<Cow<'a, Value>>,
+        added: Vec<Cow<'a, Value>>,
+    },
+    /// changes to an array of objects identified by their `key` field (see
+    /// `ArrayOptions::keyed_paths`), where each change is given under the key of its item (see
+    /// [`KeyedOrder`]): added items are appended in the order of the changes, deleted and modified
+    /// items are found by key, and the items are then put in `order` when given
+    Keyed {
+        key: String,
+        changes: Vec<(String, Delta<'a>)>,
+        order: Option<KeyedOrder>,
+    },
     None,
 }
 
+/// The keys of the items of a keyed array before and after the change, in order, where the key of
+/// an item is its `key` field, the string itself for a string, the JSON text for a number or a
+/// boolean. Only recorded when the items are reordered, i.e. when keeping the kept items in their
+/// order and appending the added ones does not give the new order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyedOrder {
+    pub left: Vec<String>,
+    pub right: Vec<String>,
+}
+
+impl KeyedOrder {
+    /// The order of the reversed change
+    pub fn reverse(self) -> KeyedOrder {
+        KeyedOrder {
+            left: self.right,
+            right: self.left,
+        }
+    }
+}
+
 impl<'a> Delta<'a> {
     pub fn added_ref(value: &'a Value) -> Delta<'a> {
         Delta::Added(Cow::Borrowed(value))
     }
 
     pub fn modified_ref(old_value: &'a Value, new_value: &'a Value) -> Delta<'a> {
-        Delta::Modified(Cow::Borrowed(old_value), Cow::Borrowed(new_value))
+        Delta::Modified(Some(Cow::Borrowed(old_value)), Cow::Borrowed(new_value))
     }
 
     pub fn deleted_ref(value: &'a Value) -> Delta<'a> {
-        Delta::Deleted(Cow::Borrowed(value))
+        Delta::Deleted(Some(Cow::Borrowed(value)))
     }
 
     pub fn moved_ref(moved_value: &'a Value, new_index: usize) -> Delta<'a> {
@@ -155,6 +237,59 @@ impl<'a> Delta<'a> {
         Delta::TextDiff(text_diff.to_string())
     }
 
+    /// Converts the delta into one that owns all its values, so that it no longer borrows from the
+    /// diffed documents. Values that are already owned (see `clone_diff_values`) are not copied.
+    pub fn into_owned(self) -> Delta<'static> {
+        fn owned(value: Cow<'_, Value>) -> Cow<'static, Value> {
+            Cow::Owned(value.into_owned())
+        }
+
+        match self {
+            Delta::Added(new_value) => Delta::Added(owned(new_value)),
+            Delta::Modified(old_value, new_value) => {
+                Delta::Modified(old_value.map(owned), owned(new_value))
+            }
+            Delta::Deleted(deleted) => Delta::Deleted(deleted.map(owned)),
+            Delta::Object(object) => Delta::Object(
+                object
+                    .into_iter()
+                    .map(|(key, delta)| (key, delta.into_owned()))
+                    .collect(),
+            ),
+            Delta::Array(array_changes) => Delta::Array(
+                array_changes
+                    .into_iter()
+                    .map(|(index, delta)| (index, delta.into_owned()))
+                    .collect(),
+            ),
+            Delta::Moved {
+                moved_value,
+                new_index,
+            } => Delta::Moved {
+                moved_value: moved_value.map(owned),
+                new_index,
+            },
+            Delta::TextDiff(text_diff) => Delta::TextDiff(text_diff),
+            Delta::Unordered { removed, added } => Delta::Unordered {
+                removed: removed.into_iter().map(owned).collect(),
+                added: added.into_iter().map(owned).collect(),
+            },
+            Delta::Keyed {
+                key,
+                changes,
+                order,
+            } => Delta::Keyed {
+                key,
+                changes: changes
+                    .into_iter()
+                    .map(|(item_key, delta)| (item_key, delta.into_owned()))
+                    .collect(),
+                order,
+            },
+            Delta::None => Delta::None,
+        }
+    }
+
     // pub fn array(array: Vec<(ArrayDeltaIndex, Delta<'a>)>) -> Delta<'a> {
     //     Delta::Array(array)
     // }
@@ -162,9 +297,14 @@ impl<'a> Delta<'a> {
     /// Reverses the delta
     pub fn build_reverse(self) -> Result<Delta<'a>, JsonDiffPatchReverseError> {
         match self {
-            Delta::Added(new_value) => Ok(Delta::Deleted(new_value)),
-            Delta::Modified(old_value, new_value) => Ok(Delta::Modified(new_value, old_value)),
-            Delta::Deleted(deleted) => Ok(Delta::Added(deleted)),
+            Delta::Added(new_value) => Ok(Delta::Deleted(Some(new_value))),
+            Delta::Modified(old_value, new_value) => {
+                let old_value = old_value.ok_or(JsonDiffPatchReverseError::MissingRemovedValue)?;
+                Ok(Delta::Modified(Some(new_value), old_value))
+            }
+            Delta::Deleted(deleted) => Ok(Delta::Added(
+                deleted.ok_or(JsonDiffPatchReverseError::MissingRemovedValue)?,
+            )),
             Delta::Object(object) => {
                 let mut reversed_changes = HashMap::new();
                 for (key, value) in object {
@@ -177,14 +317,98 @@ impl<'a> Delta<'a> {
                 moved_value: _,
                 new_index: _,
             } => Err(JsonDiffPatchReverseError::InvalidMoveDelta),
-            Delta::TextDiff(_uni_diff) => {
-                todo!()
+            Delta::TextDiff(uni_diff) => Ok(Delta::TextDiff(reverse_text_diff(&uni_diff))),
+            Delta::Unordered { removed, added } => Ok(Delta::Unordered {
+                removed: added,
+                added: removed,
+            }),
+            Delta::Keyed {
+                key,
+                changes,
+                order,
+            } => Ok(Delta::Keyed {
+                key,
+                changes: changes
+                    .into_iter()
+                    .map(|(item_key, delta)| Ok((item_key, delta.build_reverse()?)))
+                    .collect::<Result<_, JsonDiffPatchReverseError>>()?,
+                order: order.map(KeyedOrder::reverse),
+            }),
+            Delta::Array(array_changes) => {
+                let (mut reversed_changes, nested_changes) = reverse_array_changes(array_changes)?;
+                for (index, nested) in nested_changes {
+                    reversed_changes.push((
+                        ArrayDeltaIndex::NewOrModified(index),
+                        nested.build_reverse()?,
+                    ));
+                }
+                Ok(Delta::Array(reversed_changes))
+            }
+        }
+    }
+}
+
+/// Reverses the structural changes of an array delta (additions, deletions and moves).
+///
+/// Nested changes are returned separately, still to be reversed, along with the index of their
+/// item in the original array, which is where they apply in the reversed delta.
+#[allow(clippy::type_complexity)]
+pub(crate) fn reverse_array_changes(
+    array_changes: Vec<(ArrayDeltaIndex, Delta<'_>)>,
+) -> Result<(Vec<(ArrayDeltaIndex, Delta<'_>)>, Vec<(usize, Delta<'_>)>), JsonDiffPatchReverseError>
+{
+    let index_map = ArrayIndexMap::new(&array_changes);
+    let mut reversed_changes = Vec::with_capacity(array_changes.len());
+    let mut nested_changes = Vec::new();
+
+    for (index, delta) in array_changes {
+        match (index, delta) {
+            (ArrayDeltaIndex::RemovedOrMoved(old_index), Delta::Deleted(deleted)) => {
+                reversed_changes.push((
+                    ArrayDeltaIndex::NewOrModified(old_index),
+                    Delta::Added(deleted.ok_or(JsonDiffPatchReverseError::MissingRemovedValue)?),
+                ));
+            }
+            (
+                ArrayDeltaIndex::RemovedOrMoved(old_index),
+                Delta::Moved {
+                    moved_value,
+                    new_index,
+                },
+            ) => {
+                reversed_changes.push((
+                    ArrayDeltaIndex::RemovedOrMoved(new_index),
+                    Delta::Moved {
+                        moved_value,
+                        new_index: old_index,
+                    },
+                ));
             }
-            Delta::Array(_array) => {
-                todo!()
+            (ArrayDeltaIndex::RemovedOrMoved(old_index), _) => {
+                return Err(JsonDiffPatchReverseError::InvalidArrayDelta(format!(
+                    "only removal or move can be applied at original array index {}",
+                    old_index
+                )));
+            }
+            (ArrayDeltaIndex::NewOrModified(new_index), Delta::Added(added)) => {
+                reversed_changes.push((
+                    ArrayDeltaIndex::RemovedOrMoved(new_index),
+                    Delta::Deleted(Some(added)),
+                ));
+            }
+            (ArrayDeltaIndex::NewOrModified(new_index), nested) => {
+                let old_index = index_map.old_index(new_index).ok_or_else(|| {
+                    JsonDiffPatchReverseError::InvalidArrayDelta(format!(
+                        "no original item for the change at array index {}",
+                        new_index
+                    ))
+                })?;
+                nested_changes.push((old_index, nested));
             }
         }
     }
+
+    Ok((reversed_changes, nested_changes))
 }
 
 impl Serialize for Delta<'_> {
@@ -200,19 +424,33 @@ impl Serialize for Delta<'_> {
                 seq.serialize_element(new_value)?;
                 seq.end()
             }
-            Delta::Modified(old_value, new_value) => {
+            Delta::Modified(Some(old_value), new_value) => {
                 let mut seq = serializer.serialize_seq(Some(2))?;
                 seq.serialize_element(old_value)?;
                 seq.serialize_element(new_value)?;
                 seq.end()
             }
-            Delta::Deleted(deleted) => {
+            Delta::Modified(None, new_value) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&OMITTED_VALUE)?;
+                seq.serialize_element(new_value)?;
+                seq.serialize_element(&MagicNumber::ModifiedOmitted)?;
+                seq.end()
+            }
+            Delta::Deleted(Some(deleted)) => {
                 let mut seq = serializer.serialize_seq(Some(3))?;
                 seq.serialize_element(deleted)?;
                 seq.serialize_element(&MIDDLE_NO_VALUE)?;
                 seq.serialize_element(&MagicNumber::Deleted)?;
                 seq.end()
             }
+            Delta::Deleted(None) => {
+                let mut seq = serializer.serialize_seq(Some(3))?;
+                seq.serialize_element(&OMITTED_VALUE)?;
+                seq.serialize_element(&OMITTED_VALUE)?;
+                seq.serialize_element(&MagicNumber::DeletedOmitted)?;
+                seq.end()
+            }
             Delta::Object(value) => {
                 let mut map = serializer.serialize_map(Some(value.len()))?;
                 for (k, v) in value {
@@ -233,9 +471,11 @@ impl Serialize for Delta<'_> {
                 new_index,
             } => {
                 let mut seq = serializer.serialize_seq(Some(3))?;
-                seq.serialize_element(
-                    moved_value.as_ref().unwrap_or(&Cow::Borrowed(&Value::Null)),
-                )?;
+                // an empty string when the value is not included, as the JS library writes it
+                match moved_value {
+                    Some(moved_value) => seq.serialize_element(moved_value)?,
+                    None => seq.serialize_element("")?,
+                }
                 seq.serialize_element(new_index)?;
                 seq.serialize_element(&MagicNumber::ArrayMoved)?;
                 seq.end()
@@ -247,6 +487,29 @@ impl Serialize for Delta<'_> {
                 seq.serialize_element(&MagicNumber::UndefinedDiff)?;
                 seq.end()
             }
+            Delta::Unordered { removed, added } => {
+                let mut map = serializer.serialize_map(Some(3))?;
+                map.serialize_entry("_t", &Value::String("u".to_string()))?;
+                map.serialize_entry("-", removed)?;
+                map.serialize_entry("+", added)?;
+                map.end()
+            }
+            Delta::Keyed {
+                key,
+                changes,
+                order,
+            } => {
+                let mut map = serializer.serialize_map(Some(changes.len() + 3))?;
+                map.serialize_entry("_t", &Value::String("k".to_string()))?;
+                map.serialize_entry("_key", key)?;
+                if let Some(order) = order {
+                    map.serialize_entry("_order", &(&order.left, &order.right))?;
+                }
+                for (item_key, delta) in changes {
+                    map.serialize_entry(item_key, delta)?;
+                }
+                map.end()
+            }
             Delta::None => {
                 panic!("Delta::None is not serializable");
             }
@@ -254,10 +517,232 @@ impl Serialize for Delta<'_> {
     }
 }
 
+impl Delta<'static> {
+    /// Parses a delta in the jsondiffpatch JSON format, e.g. as produced by serializing a [`Delta`]
+    /// or by the original JS library.
+    ///
+    /// Removed values omitted with `omit_removed_values` are read as omitted; the `0` placeholder
+    /// of the JS library is read as an actual `0`, as nothing tells it apart from one.
+    pub fn from_value(value: Value) -> Result<Self, JsonDiffPatchDeserializeError> {
+        delta_from_value(value, "")
+    }
+}
+
+impl FromStr for Delta<'static> {
+    type Err = JsonDiffPatchDeserializeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Delta::from_value(serde_json::from_str(s)?)
+    }
+}
+
+impl<'de> Deserialize<'de> for Delta<'_> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = Value::deserialize(deserializer)?;
+        Delta::from_value(value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Appends `key` to the JSON pointer `path`, used to locate errors in malformed deltas.
+fn child_path(path: &str, key: &str) -> String {
+    format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"))
+}
+
+fn delta_from_value(
+    value: Value,
+    path: &str,
+) -> Result<Delta<'static>, JsonDiffPatchDeserializeError> {
+    match value {
+        Value::Array(items) => leaf_delta_from_array(items, path),
+        Value::Object(mut map) => match map.remove("_t") {
+            None => {
+                let mut object = HashMap::with_capacity(map.len());
+                for (key, child) in map {
+                    let child = delta_from_value(child, &child_path(path, &key))?;
+                    object.insert(key, child);
+                }
+                Ok(Delta::Object(object))
+            }
+            Some(Value::String(marker)) if marker == "a" => {
+                let mut array_changes = Vec::with_capacity(map.len());
+                for (key, child) in map {
+                    let index = key.parse::<ArrayDeltaIndex>().map_err(|_| {
+                        JsonDiffPatchDeserializeError::InvalidArrayIndex {
+                            path: path.to_string(),
+                            key: key.clone(),
+                        }
+                    })?;
+                    let child = delta_from_value(child, &child_path(path, &key))?;
+                    array_changes.push((index, child));
+                }
+                array_changes.sort_by(|(a, _), (b, _)| a.cmp(b));
+                Ok(Delta::Array(array_changes))
+            }
+            Some(Value::String(marker)) if marker == "u" => {
+                let mut items = |key: &str| match map.remove(key) {
+                    None => Ok(Vec::new()),
+                    Some(Value::Array(items)) => Ok(items.into_iter().map(Cow::Owned).collect()),
+                    Some(found) => Err(JsonDiffPatchDeserializeError::InvalidUnorderedItems {
+                        path: child_path(path, key),
+                        found,
+                    }),
+                };
+                let removed = items("-")?;
+                let added = items("+")?;
+                if let Some(key) = map.keys().next() {
+                    return Err(JsonDiffPatchDeserializeError::InvalidUnorderedKey {
+                        path: path.to_string(),
+                        key: key.clone(),
+                    });
+                }
+                Ok(Delta::Unordered { removed, added })
+            }
+            Some(Value::String(marker)) if marker == "k" => {
+                let key = match map.remove("_key") {
+                    Some(Value::String(key)) => key,
+                    found => {
+                        return Err(JsonDiffPatchDeserializeError::InvalidKeyField {
+                            path: path.to_string(),
+                            found: found.unwrap_or(Value::Null),
+                        })
+                    }
+                };
+                let order = match map.remove("_order") {
+                    None => None,
+                    Some(found) => Some(
+                        serde_json::from_value::<(Vec<String>, Vec<String>)>(found.clone())
+                            .map(|(left, right)| KeyedOrder { left, right })
+                            .map_err(|_| JsonDiffPatchDeserializeError::InvalidKeyedOrder {
+                                path: path.to_string(),
+                                found,
+                            })?,
+                    ),
+                };
+                let mut changes = Vec::with_capacity(map.len());
+                for (item_key, child) in map {
+                    let child = delta_from_value(child, &child_path(path, &item_key))?;
+                    changes.push((item_key, child));
+                }
+                Ok(Delta::Keyed {
+                    key,
+                    changes,
+                    order,
+                })
+            }
+            Some(found) => Err(JsonDiffPatchDeserializeError::InvalidArrayMarker {
+                path: path.to_string(),
+                found,
+            }),
+        },
+        found => Err(JsonDiffPatchDeserializeError::UnexpectedType {
+            path: path.to_string(),
+            found,
+        }),
+    }
+}
+
+fn leaf_delta_from_array(
+    items: Vec<Value>,
+    path: &str,
+) -> Result<Delta<'static>, JsonDiffPatchDeserializeError> {
+    let length = items.len();
+    let mut items = items.into_iter();
+    match (items.next(), items.next(), items.next()) {
+        (Some(new_value), None, None) => Ok(Delta::Added(Cow::Owned(new_value))),
+        (Some(old_value), Some(new_value), None) => Ok(Delta::Modified(
+            Some(Cow::Owned(old_value)),
+            Cow::Owned(new_value),
+        )),
+        (Some(first), Some(middle), Some(magic)) if length == 3 => {
+            let expect_placeholder = |middle: Value| match middle {
+                Value::Null => Ok(()),
+                Value::Number(ref n) if n.as_u64() == Some(0) => Ok(()),
+                found => Err(JsonDiffPatchDeserializeError::InvalidPlaceholder {
+                    path: path.to_string(),
+                    found,
+                }),
+            };
+            match magic.as_u64() {
+                Some(m) if m == MagicNumber::Deleted as u64 => {
+                    expect_placeholder(middle)?;
+                    Ok(Delta::Deleted(Some(Cow::Owned(first))))
+                }
+                Some(m) if m == MagicNumber::DeletedOmitted as u64 => {
+                    expect_placeholder(first)?;
+                    expect_placeholder(middle)?;
+                    Ok(Delta::Deleted(None))
+                }
+                Some(m) if m == MagicNumber::ModifiedOmitted as u64 => {
+                    expect_placeholder(first)?;
+                    Ok(Delta::Modified(None, Cow::Owned(middle)))
+                }
+                Some(m) if m == MagicNumber::UndefinedDiff as u64 => {
+                    expect_placeholder(middle)?;
+                    match first {
+                        Value::String(text_diff) => Ok(Delta::TextDiff(text_diff)),
+                        found => Err(JsonDiffPatchDeserializeError::InvalidTextDiff {
+                            path: path.to_string(),
+                            found,
+                        }),
+                    }
+                }
+                Some(m) if m == MagicNumber::ArrayMoved as u64 => {
+                    let new_index = middle
+                        .as_u64()
+                        .and_then(|index| usize::try_from(index).ok())
+                        .ok_or_else(|| JsonDiffPatchDeserializeError::InvalidMoveIndex {
+                            path: path.to_string(),
+                            found: middle.clone(),
+                        })?;
+                    // the JS library writes an empty string when the value is not included, so a
+                    // moved empty string is read without its value, while null is a value
+                    let moved_value = match first {
+                        Value::String(ref s) if s.is_empty() => None,
+                        value => Some(Cow::Owned(value)),
+                    };
+                    Ok(Delta::Moved {
+                        moved_value,
+                        new_index,
+                    })
+                }
+                _ => Err(JsonDiffPatchDeserializeError::UnknownMagicNumber {
+                    path: path.to_string(),
+                    magic,
+                }),
+            }
+        }
+        _ => Err(JsonDiffPatchDeserializeError::InvalidLength {
+            path: path.to_string(),
+            length,
+        }),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ArrayOptions {
     pub detect_move: Option<bool>,
     pub include_value_on_move: Option<bool>,
+    /// How items of the two arrays are paired up, the longest common subsequence by default.
+    /// `match_by_position` takes precedence.
+    pub algorithm: Option<ArrayDiffAlgorithm>,
+    /// Pairs up the remaining removed and added items whose similarity (the fraction of equal
+    /// leaf paths, between 0 and 1) is at least this threshold, so that they get a nested delta
+    /// rather than being replaced. With `detect_move`, moved items are paired the same way.
+    pub similarity_threshold: Option<f64>,
+    /// Compares all arrays as multisets, where only additions and removals of items matter. With
+    /// `omit_removed_values`, the removed items are given by index in an array delta instead.
+    pub unordered: Option<bool>,
+    /// Compares the arrays at these paths as multisets, given as JSON pointers where `*` matches
+    /// any key or index, e.g. `/users/*/permissions`
+    pub unordered_paths: Option<Vec<String>>,
+    /// Compares the arrays of objects at these paths (given as for `unordered_paths`) as maps, by
+    /// the field each path is mapped to, e.g. `{"/spec/containers": "name"}`. The order of the
+    /// items is recorded only when they are reordered (see [`KeyedOrder`]). Falls back to the
+    /// other modes when an item has no string, number or boolean key, or when keys are repeated.
+    pub keyed_paths: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -265,21 +750,34 @@ pub struct TextDiffOptions {
     pub min_length: Option<usize>,
 }
 
+impl Default for ArrayOptions {
+    fn default() -> Self {
+        Self {
+            detect_move: Some(true),
+            include_value_on_move: Some(false),
+            algorithm: Some(ArrayDiffAlgorithm::Lcs),
+            similarity_threshold: None,
+            unordered: Some(false),
+            unordered_paths: None,
+            keyed_paths: None,
+        }
+    }
+}
+
 impl Default for Options {
     fn default() -> Self {
         Self {
-            // object_hash: None,
+            object_hash: None,
             match_by_position: Some(false),
-            arrays: Some(ArrayOptions {
-                detect_move: Some(true),
-                include_value_on_move: Some(false),
-            }),
+            arrays: Some(ArrayOptions::default()),
             text_diff: Some(TextDiffOptions {
                 min_length: Some(60),
             }),
-            // property_filter: None,
+            property_filter: None,
             clone_diff_values: Some(false),
             omit_removed_values: Some(false),
+            max_depth: None,
+            hash_subtrees: Some(false),
         }
     }
 }
@@ -372,3 +870,140 @@ fn test_my_delta_to_serializable() {
         .unwrap(),
     );
 }
+
+#[test]
+fn test_delta_from_value_round_trip() {
+    let serialized = serde_json::json!({
+        "a":["added"],
+        "b":["old","new"],
+        "c":["deleted",null,0],
+        "d":["moved",1,3],
+        "e":["text_diff",null,2],
+        "f":{
+            "5":["added"],
+            "_7":["deleted",0,0],
+            "_8":["",1,3],
+            "_t":"a"
+        },
+        "g":{
+            "h":["added"],
+            "l":["text_diff",0,2]
+        }
+    });
+
+    let delta = Delta::from_value(serialized).unwrap();
+    let Delta::Object(ref object) = delta else {
+        panic!("expected an object delta, got {:?}", delta);
+    };
+    assert!(matches!(object["f"], Delta::Array(ref changes) if changes.len() == 3));
+    assert!(matches!(
+        object["d"],
+        Delta::Moved {
+            moved_value: Some(_),
+            new_index: 1
+        }
+    ));
+
+    assert_eq!(
+        serde_json::to_value(&delta).unwrap(),
+        serde_json::json!({
+            "a":["added"],
+            "b":["old","new"],
+            "c":["deleted",null,0],
+            "d":["moved",1,3],
+            "e":["text_diff",null,2],
+            "f":{
+                "5":["added"],
+                "_7":["deleted",null,0],
+                "_8":["",1,3],
+                "_t":"a"
+            },
+            "g":{
+                "h":["added"],
+                "l":["text_diff",null,2]
+            }
+        })
+    );
+
+    let left = serde_json::json!({"name": "John", "tags": ["a", "b", "c"]});
+    let delta: Delta = serde_json::from_str(
+        r#"{"name":["John","Jane"],"tags":{"_t":"a","_0":["",2,3],"1":["x"]}}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        crate::patch(&left, delta).unwrap(),
+        serde_json::json!({"name": "Jane", "tags": ["b", "x", "a", "c"]})
+    );
+
+    // a moved null is a value, unlike the empty string written when there is none
+    for moved_value in [Some(Cow::Owned(Value::Null)), None] {
+        let delta = Delta::Moved {
+            moved_value: moved_value.clone(),
+            new_index: 2,
+        };
+        let read = Delta::from_value(serde_json::to_value(&delta).unwrap()).unwrap();
+        assert!(
+            matches!(&read, Delta::Moved { moved_value: read_value, new_index: 2 }
+                if *read_value == moved_value),
+            "{:?}",
+            read
+        );
+    }
+}
+
+#[test]
+fn test_delta_from_value_errors() {
+    let cases = [
+        (
+            r#"{"a":5}"#,
+            "invalid delta at '/a': expected a JSON array or object, found 5",
+        ),
+        (
+            r#"{"a":[]}"#,
+            "invalid delta at '/a': expected an array of 1, 2 or 3 elements, found 0",
+        ),
+        (
+            r#"[1,2,3,4]"#,
+            "invalid delta at '': expected an array of 1, 2 or 3 elements, found 4",
+        ),
+        (
+            r#"{"a/b":[1,0,9]}"#,
+            "invalid delta at '/a~1b': unknown magic number 9",
+        ),
+        (
+            r#"[1,7,0]"#,
+            "invalid delta at '': expected 0 or null as the placeholder element, found 7",
+        ),
+        (
+            r#"[1,0,2]"#,
+            "invalid delta at '': text diff must be a string, found 1",
+        ),
+        (
+            r#"{"_t":"a","_1":["",-1,3]}"#,
+            "invalid delta at '/_1': move target must be a non-negative integer, found -1",
+        ),
+        (
+            r#"{"_t":"x"}"#,
+            "invalid delta at '': array type marker must be \"a\", \"u\" or \"k\", found \"x\"",
+        ),
+        (
+            r#"{"_t":"u","-":1}"#,
+            "invalid delta at '/-': unordered array items must be listed in an array, found 1",
+        ),
+        (
+            r#"{"_t":"u","+":[1],"0":[1]}"#,
+            "invalid delta at '': unexpected key '0' in an unordered array delta",
+        ),
+        (
+            r#"{"_t":"a","x1":[1]}"#,
+            "invalid delta at '': 'x1' is not a valid array delta index",
+        ),
+    ];
+
+    for (input, expected) in cases {
+        let err = input.parse::<Delta>().unwrap_err();
+        assert_eq!(err.to_string(), expected, "input: {}", input);
+    }
+
+    assert!(serde_json::from_str::<Delta>(r#"{"a":[1,0,9]}"#).is_err());
+}